@@ -6,10 +6,17 @@ use std::{
 use anyhow::Result;
 use axum::{
     extract::State,
+    middleware,
     routing::{get, patch},
     Json, Router,
 };
 use derive_builder::Builder;
+use ecosystem::{
+    api_docs,
+    health::{track_metrics, Health, HealthApi},
+    rate_limit::{rate_limit, Quota, RateLimiter},
+    shutdown::Shutdown,
+};
 use serde::{Deserialize, Serialize};
 use tracing::{info, instrument, level_filters::LevelFilter};
 use tracing_subscriber::{
@@ -18,8 +25,9 @@ use tracing_subscriber::{
     util::SubscriberInitExt,
     Layer,
 };
+use utoipa::{OpenApi, ToSchema};
 
-#[derive(Debug, Clone, Serialize, Builder)]
+#[derive(Debug, Clone, Serialize, Builder, ToSchema)]
 struct User {
     #[builder(setter(into))]
     name: String,
@@ -31,12 +39,43 @@ struct User {
     skills: Vec<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// Fields to change, the others are kept.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 struct UserUpdate {
     age: Option<u8>,
     skills: Option<Vec<String>>,
 }
 
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "user service", description = "Shows and updates a user."),
+    paths(user_handler, update_handler),
+    tags((name = "user", description = "The one user of the service"))
+)]
+struct ApiDoc;
+
+// the user service, its health checks and api docs
+fn app(user: User) -> Router {
+    // nothing to depend on, ready as soon as it's up
+    let health = Health::new();
+    Router::new()
+        .route("/", get(user_handler))
+        .route("/", patch(update_handler))
+        .layer(middleware::from_fn_with_state(
+            RateLimiter::new(Quota::per_minute(60)),
+            rate_limit,
+        ))
+        .merge(health.routes())
+        .merge(api_docs::routes(
+            ApiDoc::openapi().merge_from(HealthApi::openapi()),
+        ))
+        .layer(middleware::from_fn_with_state(
+            health.metrics(),
+            track_metrics,
+        ))
+        .with_state(Arc::new(Mutex::new(user)))
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let console = fmt::layer()
@@ -54,25 +93,46 @@ async fn main() -> Result<()> {
         .skill("C++")
         .build()?;
 
-    let shared_state = Arc::new(Mutex::new(user));
-
     let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
-    let app = Router::new()
-        .route("/", get(user_handler))
-        .route("/", patch(update_handler))
-        .with_state(shared_state);
+    let app = app(user);
     let listener = tokio::net::TcpListener::bind(addr).await?;
     info!("Serving on {:?}", addr);
-    axum::serve(listener, app).await?;
+    let shutdown = Shutdown::default().on_signal();
+    // clients are rate limited by ip
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.clone().started());
+    if let Some(ret) = shutdown.drain(server).await {
+        ret?;
+    }
 
     Ok(())
 }
 
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "user",
+    responses((status = 200, description = "The user", body = User))
+)]
 #[instrument]
 async fn user_handler(State(user): State<Arc<Mutex<User>>>) -> Json<User> {
     (*user.lock().unwrap()).clone().into()
 }
 
+#[utoipa::path(
+    patch,
+    path = "/",
+    tag = "user",
+    request_body(content = UserUpdate, example = json!({"skills": ["Ruby"]})),
+    responses(
+        (status = 200, description = "The updated user", body = User),
+        (status = 400, description = "Malformed json", body = String, content_type = "text/plain"),
+        (status = 422, description = "Json that isn't an update", body = String, content_type = "text/plain"),
+    )
+)]
 #[instrument]
 async fn update_handler(
     State(user): State<Arc<Mutex<User>>>,
@@ -87,3 +147,89 @@ async fn update_handler(
     }
     (*user).clone().into()
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+    };
+    use ecosystem::api_docs::assert_documented;
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn openapi_spec_should_match_the_handlers() -> Result<()> {
+        let user = UserBuilder::default().name("Alice").build()?;
+        let app = app(user);
+        let (status, spec) = send(&app, Request::get("/openapi.json").body(Body::empty())?).await?;
+        assert_eq!(status, StatusCode::OK);
+        let expected = ApiDoc::openapi().merge_from(HealthApi::openapi());
+        assert_eq!(spec, serde_json::to_value(expected)?);
+        assert_eq!(spec["openapi"], "3.1.0");
+
+        // every documented operation is served, answering as documented to a
+        // request made of its example
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                let at = format!("{} {path}", method.to_uppercase());
+                let req = Request::builder()
+                    .method(method.to_uppercase().as_str())
+                    .uri(path);
+                let req = match operation["requestBody"]["content"].get("application/json") {
+                    None => req.body(Body::empty())?,
+                    Some(media) => {
+                        let example = &media["example"];
+                        assert!(!example.is_null(), "{at}: request body without an example");
+                        assert_documented(&spec, &media["schema"], example, &at);
+                        req.header(CONTENT_TYPE, "application/json")
+                            .body(Body::from(example.to_string()))?
+                    }
+                };
+                let (status, body) = send(&app, req).await?;
+                assert!(status.is_success(), "{at} answered {status}");
+                let documented = &operation["responses"][status.as_str()];
+                assert!(
+                    !documented.is_null(),
+                    "{at} answered an undocumented {status}"
+                );
+                if let Some(media) = documented["content"].get("application/json") {
+                    assert_documented(&spec, &media["schema"], &body, &at);
+                }
+            }
+        }
+
+        // and what isn't documented isn't served
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            for method in ["GET", "POST", "PUT", "PATCH", "DELETE"] {
+                if item.get(method.to_lowercase()).is_some() {
+                    continue;
+                }
+                let req = Request::builder()
+                    .method(method)
+                    .uri(path)
+                    .body(Body::empty())?;
+                let (status, _) = send(&app, req).await?;
+                assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED, "{method} {path}");
+            }
+        }
+
+        // the example update was applied
+        let (_, user) = send(&app, Request::get("/").body(Body::empty())?).await?;
+        assert_eq!(user["skills"], json!(["Ruby"]));
+        Ok(())
+    }
+
+    // the status and body of a response, json if it is json, text otherwise
+    async fn send(app: &Router, req: Request<Body>) -> Result<(StatusCode, Value)> {
+        let res = app.clone().oneshot(req).await?;
+        let status = res.status();
+        let bytes = res.into_body().collect().await?.to_bytes();
+        let body = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+        Ok((status, body))
+    }
+}
@@ -1,5 +1,9 @@
 use anyhow::Result;
-use axum::{routing::get, Router};
+use axum::{middleware, routing::get, Router};
+use ecosystem::{
+    health::{track_metrics, Health},
+    shutdown::Shutdown,
+};
 use opentelemetry::{trace::TracerProvider as _, KeyValue};
 use opentelemetry_otlp::WithExportConfig;
 use opentelemetry_sdk::{
@@ -20,7 +24,7 @@ use tracing_subscriber::{
 #[tokio::main]
 async fn main() -> Result<()> {
     let file_appender = tracing_appender::rolling::hourly("/tmp/logs", "ecosystem.log");
-    let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
+    let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
 
     let console = fmt::layer()
         .with_span_events(FmtSpan::CLOSE)
@@ -45,11 +49,27 @@ async fn main() -> Result<()> {
         .init();
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
-    let app = Router::new().route("/", get(index_handler));
+    let health = Health::new();
+    let app = Router::new()
+        .route("/", get(index_handler))
+        .merge(health.routes())
+        .layer(middleware::from_fn_with_state(
+            health.metrics(),
+            track_metrics,
+        ));
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
     info!("Serving on {:?}", addr);
-    axum::serve(listener, app).await?;
+    let shutdown = Shutdown::default().on_signal();
+    let server = axum::serve(listener, app).with_graceful_shutdown(shutdown.clone().started());
+    let ret = shutdown.drain(server).await;
+
+    // export the spans still batched and write out the buffered log lines, even if serving failed
+    if let Err(e) = provider.shutdown() {
+        warn!("Failed to flush spans: {}", e);
+    }
+    drop(guard);
+    ret.transpose()?;
 
     Ok(())
 }
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use ecosystem::shutdown::Shutdown;
 use tokio::net::{TcpListener, TcpStream};
 use tracing::{info, level_filters::LevelFilter, warn};
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, Layer};
@@ -18,17 +19,25 @@ async fn main() -> Result<()> {
     let config = resolve_config();
     let config = Arc::new(config);
     let listener = TcpListener::bind(&config.listener_addr).await?;
+    let shutdown = Shutdown::default().on_signal();
 
     loop {
-        let (client, addr) = listener.accept().await?;
+        let (client, addr) = tokio::select! {
+            ret = listener.accept() => ret?,
+            _ = shutdown.token().cancelled() => break,
+        };
         info!("Accept connection from {}", addr);
         let config_cloned = Arc::clone(&config);
-        tokio::spawn(async move {
+        shutdown.spawn(async move {
             let upstream = TcpStream::connect(&config_cloned.upstream_addr).await?;
             proxy(client, upstream).await?;
             Ok::<(), anyhow::Error>(())
         });
     }
+
+    // open connections get the grace period to finish, then they are cut
+    shutdown.wait([]).await;
+    Ok(())
 }
 
 async fn proxy(mut client: TcpStream, mut upstream: TcpStream) -> Result<()> {
@@ -0,0 +1,327 @@
+use std::io::{self, BufRead};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use ecosystem::shortener::{
+    BulkItem, Client, LinkSort, ListLinksReq, ShortenReq, SortOrder, StatsRes,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+// the most urls the server shortens in one bulk request
+const BULK_CHUNK: usize = 1000;
+
+/// command line client of the url shortener
+#[derive(Debug, Parser)]
+struct Cli {
+    /// the shortener to talk to
+    #[arg(long, env = "SHORTENER_URL", default_value = "http://localhost:9876")]
+    server: String,
+    /// api key owning the links created, needed to delete them
+    #[arg(long, env = "SHORTENER_API_KEY", hide_env_values = true)]
+    api_key: Option<String>,
+    /// admin token, needed to list links
+    #[arg(long, env = "SHORTENER_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+    #[arg(long, value_enum, default_value_t = Output::Table)]
+    output: Output,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Output {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Shorten a url, or every url read from stdin (one per line) without one
+    Create(CreateArgs),
+    /// Show where a short url (or id) points, without counting a visit
+    Resolve { id: String },
+    /// Clicks of a link per day
+    Stats { id: String },
+    /// Links stored on the server, with the admin token
+    List(ListArgs),
+    /// Delete a link owned by the api key
+    Delete { id: String },
+}
+
+#[derive(Debug, Args)]
+struct CreateArgs {
+    url: Option<String>,
+    #[arg(long, requires = "url")]
+    alias: Option<String>,
+    /// e.g. 2030-01-01T00:00:00Z
+    #[arg(long, conflicts_with = "expires_in")]
+    expires_at: Option<DateTime<Utc>>,
+    /// from now, e.g. 90m, 12h or 7d
+    #[arg(long, value_parser = parse_duration)]
+    expires_in: Option<Duration>,
+}
+
+#[derive(Debug, Args)]
+struct ListArgs {
+    /// substring of the target url
+    #[arg(long)]
+    q: Option<String>,
+    /// host of the target url, subdomains included
+    #[arg(long)]
+    domain: Option<String>,
+    /// `created_at` or `clicks`
+    #[arg(long, value_parser = parse_serde::<LinkSort>, default_value = "created_at")]
+    sort: LinkSort,
+    /// `asc` or `desc`
+    #[arg(long, value_parser = parse_serde::<SortOrder>, default_value = "desc")]
+    order: SortOrder,
+    #[arg(long)]
+    limit: Option<u32>,
+    /// `next_cursor` of the previous page
+    #[arg(long)]
+    cursor: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let mut client = Client::new(&cli.server)?;
+    if let Some(key) = &cli.api_key {
+        client = client.with_api_key(key);
+    }
+    if let Some(token) = &cli.admin_token {
+        client = client.with_admin_token(token);
+    }
+
+    match cli.command {
+        Command::Create(args) => create(&client, args, cli.output).await,
+        Command::Resolve { id } => {
+            let res = client.resolve(client.id_of(&id)).await?;
+            let location = res.location.as_deref().unwrap_or("(shows a page)");
+            print(cli.output, &res, || {
+                table(
+                    &["ID", "STATUS", "LOCATION"],
+                    [[res.id.clone(), res.status.to_string(), location.to_string()]],
+                )
+            })
+        }
+        Command::Stats { id } => {
+            let stats = client.stats(client.id_of(&id)).await?;
+            print(cli.output, &stats, || stats_table(&stats))
+        }
+        Command::List(args) => {
+            let req = ListLinksReq {
+                q: args.q,
+                domain: args.domain,
+                sort: args.sort,
+                order: args.order,
+                limit: args.limit,
+                cursor: args.cursor,
+            };
+            let res = client.list_links(&req).await?;
+            print(cli.output, &res, || {
+                let rows = res.links.iter().map(|link| {
+                    [
+                        link.id.clone(),
+                        link.clicks.to_string(),
+                        link.created_at.format("%Y-%m-%d %H:%M").to_string(),
+                        link.expires_at
+                            .map_or("-".to_string(), |e| e.format("%Y-%m-%d %H:%M").to_string()),
+                        link.url.clone(),
+                    ]
+                });
+                let mut out = table(&["ID", "CLICKS", "CREATED", "EXPIRES", "URL"], rows);
+                if let Some(cursor) = &res.next_cursor {
+                    out.push_str(&format!("\nmore with --cursor {cursor}\n"));
+                }
+                out
+            })
+        }
+        Command::Delete { id } => {
+            let id = client.id_of(&id);
+            client.delete(id).await?;
+            let res = serde_json::json!({ "id": id, "deleted": true });
+            print(cli.output, &res, || format!("deleted {id}\n"))
+        }
+    }
+}
+
+async fn create(client: &Client, args: CreateArgs, output: Output) -> Result<()> {
+    let expires_at = match args.expires_in {
+        Some(expires_in) => Some(
+            Utc::now()
+                .checked_add_signed(expires_in)
+                .ok_or_else(|| anyhow!("--expires-in {expires_in} is too far in the future"))?,
+        ),
+        None => args.expires_at,
+    };
+    if let Some(url) = args.url.filter(|url| url != "-") {
+        let req = ShortenReq {
+            url,
+            alias: args.alias,
+            expires_at,
+            ..Default::default()
+        };
+        let res = client.shorten(&req).await?;
+        return print(output, &res, || format!("{}\n", res.url));
+    }
+
+    let mut urls = vec![];
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let url = line.trim();
+        if !url.is_empty() {
+            urls.push(url.to_string());
+        }
+    }
+    let mut results: Vec<BulkItem> = vec![];
+    for (chunk_index, chunk) in urls.chunks(BULK_CHUNK).enumerate() {
+        let reqs: Vec<_> = chunk
+            .iter()
+            .map(|url| ShortenReq {
+                url: url.clone(),
+                expires_at,
+                ..Default::default()
+            })
+            .collect();
+        let res = client.bulk_shorten(&reqs).await?;
+        // rows are numbered per request, number them across the whole input
+        results.extend(res.results.into_iter().map(|mut item| {
+            item.index += chunk_index * BULK_CHUNK;
+            item
+        }));
+    }
+    print(output, &results, || {
+        let rows = results.iter().map(|item| {
+            [
+                item.url.clone(),
+                item.short_url
+                    .clone()
+                    .or(item.error.clone())
+                    .unwrap_or_default(),
+            ]
+        });
+        table(&["URL", "SHORT URL"], rows)
+    })?;
+    let failed = results.iter().filter(|item| item.error.is_some()).count();
+    if failed > 0 {
+        bail!("{failed} of {} urls could not be shortened", results.len());
+    }
+    Ok(())
+}
+
+fn print<T: Serialize>(output: Output, value: &T, table: impl FnOnce() -> String) -> Result<()> {
+    match output {
+        Output::Json => println!("{}", serde_json::to_string_pretty(value)?),
+        Output::Table => print!("{}", table()),
+    }
+    Ok(())
+}
+
+fn stats_table(stats: &StatsRes) -> String {
+    let rows = stats
+        .days
+        .iter()
+        .map(|day| [day.day.to_string(), day.clicks.to_string()])
+        .chain([["total".to_string(), stats.total.to_string()]]);
+    let mut out = table(&["DAY", "CLICKS"], rows);
+    if !stats.variants.is_empty() {
+        let rows = stats
+            .variants
+            .iter()
+            .map(|variant| [variant.variant.clone(), variant.clicks.to_string()]);
+        out.push('\n');
+        out.push_str(&table(&["VARIANT", "CLICKS"], rows));
+    }
+    out
+}
+
+// left aligned columns as wide as their widest cell, the last one unpadded
+fn table<const N: usize>(
+    header: &[&str; N],
+    rows: impl IntoIterator<Item = [String; N]>,
+) -> String {
+    let header = header.map(str::to_string);
+    let rows: Vec<_> = std::iter::once(header).chain(rows).collect();
+    let mut widths = [0; N];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    for row in &rows {
+        let cells: Vec<_> = row
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(i, (cell, width))| {
+                if i + 1 == N {
+                    cell.clone()
+                } else {
+                    format!("{cell:<width$}")
+                }
+            })
+            .collect();
+        out.push_str(&cells.join("  "));
+        out.push('\n');
+    }
+    out
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let usage = || format!("expected e.g. 90m, 12h or 7d, not {s:?}");
+    let (at, unit) = s.char_indices().last().ok_or_else(usage)?;
+    let amount: i64 = s[..at].parse().map_err(|_| usage())?;
+    if amount <= 0 {
+        return Err(format!("{s:?} must be longer than zero"));
+    }
+    let duration = match unit {
+        's' => Duration::try_seconds(amount),
+        'm' => Duration::try_minutes(amount),
+        'h' => Duration::try_hours(amount),
+        'd' => Duration::try_days(amount),
+        _ => return Err(format!("unit must be s, m, h or d, not {unit:?}")),
+    };
+    duration.ok_or_else(|| format!("{s:?} is too long"))
+}
+
+// the api's own spelling of its enums, e.g. `created_at`
+fn parse_serde<T: DeserializeOwned>(s: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(s.to_string())).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_should_read_amount_and_unit() {
+        assert_eq!(parse_duration("90m"), Ok(Duration::minutes(90)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::hours(12)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::days(7)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::seconds(30)));
+    }
+
+    #[test]
+    fn parse_duration_should_reject_bad_input() {
+        for s in [
+            "", "d", "5", "5w", "5é", "é", "x5d", "5 d", "-5d", "0h", "-0s",
+        ] {
+            assert!(parse_duration(s).is_err(), "{s:?} parsed");
+        }
+    }
+
+    #[test]
+    fn parse_duration_should_reject_huge_amounts() {
+        for s in [
+            "999999999999d",
+            "9999999999999999h",
+            "9223372036854775807s",
+            "99999999999999999999m",
+        ] {
+            assert!(parse_duration(s).is_err(), "{s:?} parsed");
+        }
+    }
+}
@@ -1,12 +1,17 @@
 use anyhow::Result;
 use dashmap::DashMap;
+use ecosystem::shutdown::Shutdown;
 use futures::{stream::SplitStream, SinkExt, StreamExt};
 use std::{fmt, net::SocketAddr, sync::Arc};
 use tokio::{
     net::{TcpListener, TcpStream},
     sync::mpsc,
 };
-use tokio_util::codec::{Framed, LinesCodec};
+use tokio_util::{
+    codec::{Framed, LinesCodec},
+    sync::CancellationToken,
+    task::TaskTracker,
+};
 use tracing::{info, level_filters::LevelFilter, warn};
 use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
 
@@ -14,6 +19,8 @@ const MAX_MESSAGE: usize = 128;
 #[derive(Debug, Default)]
 struct State {
     peers: DashMap<SocketAddr, mpsc::Sender<Arc<Message>>>,
+    // the tasks writing to the peers
+    writers: TaskTracker,
 }
 
 struct Peer {
@@ -26,6 +33,7 @@ enum Message {
     UserJoined(String),
     UserLeft(String),
     Chat { sender: String, content: String },
+    ServerShutdown,
 }
 
 #[tokio::main]
@@ -37,27 +45,48 @@ async fn main() -> Result<()> {
     let listener = TcpListener::bind(addr).await?;
     info!("Serving on {:?}", addr);
     let state = Arc::new(State::default());
+    let shutdown = Shutdown::default().on_signal();
 
     loop {
-        let (stream, addr) = listener.accept().await?;
+        let (stream, addr) = tokio::select! {
+            ret = listener.accept() => ret?,
+            _ = shutdown.token().cancelled() => break,
+        };
         info!("Accept connection from {}", addr);
         let state_cloned = state.clone();
-        tokio::spawn(async move {
-            if let Err(e) = handle_client(state_cloned, addr, stream).await {
+        let token = shutdown.token().clone();
+        shutdown.spawn(async move {
+            if let Err(e) = handle_client(state_cloned, addr, stream, token).await {
                 warn!("Error to handle client: {}: {}", addr, e);
             }
         });
     }
+
+    // say goodbye, the connections close once the writers sent everything queued
+    state.close(Arc::new(Message::ServerShutdown));
+    shutdown.wait([&state.writers]).await;
+    Ok(())
 }
 
-async fn handle_client(state: Arc<State>, addr: SocketAddr, stream: TcpStream) -> Result<()> {
+async fn handle_client(
+    state: Arc<State>,
+    addr: SocketAddr,
+    stream: TcpStream,
+    token: CancellationToken,
+) -> Result<()> {
     let mut stream = Framed::new(stream, LinesCodec::new());
     stream.send("Enter your username:").await?;
 
-    let username = match stream.next().await {
-        Some(Ok(username)) => username,
-        Some(Err(e)) => return Err(e.into()),
-        None => return Ok(()),
+    let username = tokio::select! {
+        username = stream.next() => match username {
+            Some(Ok(username)) => username,
+            Some(Err(e)) => return Err(e.into()),
+            None => return Ok(()),
+        },
+        _ = token.cancelled() => {
+            stream.send(Message::ServerShutdown.to_string()).await?;
+            return Ok(());
+        }
     };
 
     let mut peer = state.add(addr, username, stream).await;
@@ -65,7 +94,15 @@ async fn handle_client(state: Arc<State>, addr: SocketAddr, stream: TcpStream) -
     info!("{}", message);
     state.broadcast(addr, message).await;
 
-    while let Some(line) = peer.stream.next().await {
+    loop {
+        let line = tokio::select! {
+            line = peer.stream.next() => line,
+            // the server said goodbye for everyone
+            _ = token.cancelled() => return Ok(()),
+        };
+        let Some(line) = line else {
+            break;
+        };
         let line = match line {
             Ok(line) => line,
             Err(e) => {
@@ -101,6 +138,16 @@ impl State {
         }
     }
 
+    // sends everyone a last message, then lets go of the writers so they finish
+    fn close(&self, message: Arc<Message>) {
+        for peer in self.peers.iter() {
+            if let Err(e) = peer.value().try_send(message.clone()) {
+                warn!("Failed to send message to {}: {}", peer.key(), e);
+            }
+        }
+        self.peers.clear();
+    }
+
     async fn add(
         &self,
         addr: SocketAddr,
@@ -112,7 +159,7 @@ impl State {
 
         let (mut stream_sender, stream_receiver) = stream.split();
 
-        tokio::spawn(async move {
+        self.writers.spawn(async move {
             while let Some(message) = rx.recv().await {
                 if let Err(e) = stream_sender.send(message.to_string()).await {
                     warn!("Failed to send message to {}: {}", addr, e);
@@ -153,6 +200,7 @@ impl fmt::Display for Message {
             Message::UserJoined(username) => write!(f, "[{}] joined the chat", username),
             Message::UserLeft(username) => write!(f, "[{} :(] left the chat", username),
             Message::Chat { sender, content } => write!(f, "{}: {}", sender, content),
+            Message::ServerShutdown => write!(f, "server is shutting down, bye"),
         }
     }
 }
@@ -7,13 +7,16 @@ use chacha20poly1305::{
     ChaCha20Poly1305,
 };
 use chrono::{DateTime, Utc};
+use schemars::{schema_for, JsonSchema};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 
 const KEY: &[u8] = b"01234567890123456789012345678901";
+// url safe base64 without padding, as `b64_encode` and `encypt` write it
+const BASE64URL: &str = "^[A-Za-z0-9_-]*$";
 
 #[serde_as]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct User {
     #[serde(rename = "lastName")]
@@ -23,21 +26,29 @@ struct User {
     skills: Vec<String>,
     date_of_birth: DateTime<Utc>,
     state: WorkState,
+    /// the bytes in url safe base64, without padding
     #[serde(serialize_with = "b64_encode", deserialize_with = "b64_decode")]
+    #[schemars(with = "String", extend("contentEncoding" = "base64url", "pattern" = BASE64URL))]
     data: Vec<u8>,
     // #[serde(
     //     serialize_with = "process_encrypt",
     //     deserialize_with = "process_decrypt"
     // )]
+    /// ChaCha20-Poly1305 ciphertext after its 12 byte nonce, in url safe
+    /// base64 without padding; at least the nonce and the 16 byte tag
     #[serde_as(as = "DisplayFromStr")]
+    #[schemars(extend("contentEncoding" = "base64url", "pattern" = BASE64URL, "minLength" = 38))]
     encrypted_data: EncryptedData,
+    /// 0 to 255, written as a string
     #[serde_as(as = "DisplayFromStr")]
+    #[schemars(extend("pattern" = r"^\+?(25[0-5]|2[0-4][0-9]|1?[0-9]?[0-9])$"))]
     bar: u8,
     #[serde_as(as = "Vec<DisplayFromStr>")]
+    #[schemars(extend("items" = { "type": "string", "format": "uri-reference" }))]
     url: Vec<http::Uri>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase", tag = "type", content = "details")]
 enum WorkState {
     Working(String),
@@ -48,20 +59,15 @@ enum WorkState {
 #[derive(Debug)]
 struct EncryptedData(String);
 
+/// Prints a sample user as json, or with `schema` the JSON Schema of users,
+/// e.g. to validate payloads or generate TypeScript types from.
 fn main() -> Result<()> {
-    let user = User {
-        name: "Alice".to_string(),
-        email: Some("alice@example.com".to_string()),
-        age: 30,
-        skills: vec!["Rust".to_string(), "C++".to_string()],
-        date_of_birth: Utc::now(),
-        state: WorkState::OnLeave(Utc::now()),
-        data: b"Hello, World!".to_vec(),
-        encrypted_data: EncryptedData::new("yu tian"),
-        bar: 42,
-        url: vec!["http://example.com".parse()?],
-    };
+    if std::env::args().nth(1).as_deref() == Some("schema") {
+        println!("{}", serde_json::to_string_pretty(&schema_for!(User))?);
+        return Ok(());
+    }
 
+    let user = sample(WorkState::OnLeave(Utc::now()))?;
     let json = serde_json::to_string(&user)?;
     println!("{}", json);
 
@@ -72,6 +78,21 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+fn sample(state: WorkState) -> Result<User> {
+    Ok(User {
+        name: "Alice".to_string(),
+        email: Some("alice@example.com".to_string()),
+        age: 30,
+        skills: vec!["Rust".to_string(), "C++".to_string()],
+        date_of_birth: Utc::now(),
+        state,
+        data: b"Hello, World!".to_vec(),
+        encrypted_data: EncryptedData::new("yu tian"),
+        bar: 42,
+        url: vec!["http://example.com".parse()?],
+    })
+}
+
 fn b64_encode<S>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
@@ -149,3 +170,57 @@ impl EncryptedData {
         Self(data.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn schema_should_validate_serialized_users() -> Result<()> {
+        let schema = serde_json::to_value(schema_for!(User))?;
+        let validator = jsonschema::options()
+            .should_validate_formats(true)
+            .build(&schema)?;
+        for state in [
+            WorkState::Working("shipping".to_string()),
+            WorkState::OnLeave(Utc::now()),
+            WorkState::Terminated,
+        ] {
+            let user = serde_json::to_value(sample(state)?)?;
+            let errors: Vec<_> = validator
+                .iter_errors(&user)
+                .map(|e| e.to_string())
+                .collect();
+            assert!(
+                errors.is_empty(),
+                "{user} doesn't match its schema: {errors:?}"
+            );
+        }
+
+        // and it is strict enough to catch payloads serde would reject
+        let user = serde_json::to_value(sample(WorkState::Terminated)?)?;
+        let mut max = user.clone();
+        max["bar"] = json!("255");
+        assert!(validator.is_valid(&max));
+        let wrong = [
+            ("bar", json!(42)),
+            ("bar", json!("256")),
+            ("data", json!("SGVsbG8=")),
+            ("encryptedData", json!("c2hvcnQ")),
+            ("state", json!({ "type": "working" })),
+            ("dateOfBirth", json!("yesterday")),
+        ];
+        for (field, value) in wrong {
+            let mut user = user.clone();
+            user[field] = value;
+            assert!(
+                !validator.is_valid(&user),
+                "{field}: {} passed",
+                user[field]
+            );
+        }
+        Ok(())
+    }
+}
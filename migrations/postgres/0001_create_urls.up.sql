@@ -0,0 +1,22 @@
+-- databases created before versioned migrations already have (part of) this
+-- schema, every statement is safe to run against them
+CREATE TABLE IF NOT EXISTS urls (
+    id VARCHAR(32) PRIMARY KEY,
+    url TEXT NOT NULL
+);
+
+-- tables created before aliases were supported only hold 6 char ids
+ALTER TABLE urls ALTER COLUMN id TYPE VARCHAR(32);
+
+ALTER TABLE urls
+    ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS max_clicks INTEGER,
+    ADD COLUMN IF NOT EXISTS clicks INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS owner VARCHAR(32) NOT NULL DEFAULT '',
+    ADD COLUMN IF NOT EXISTS redirect SMALLINT;
+
+-- only permanent links are deduplicated by url (per owner, '' being anonymous),
+-- limited links always get a fresh id
+ALTER TABLE urls DROP CONSTRAINT IF EXISTS urls_url_key;
+CREATE UNIQUE INDEX IF NOT EXISTS urls_permanent_url_owner_key ON urls (url, owner)
+    WHERE expires_at IS NULL AND max_clicks IS NULL;
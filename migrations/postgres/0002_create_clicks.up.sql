@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS clicks (
+    id BIGSERIAL PRIMARY KEY,
+    url_id VARCHAR(32) NOT NULL REFERENCES urls (id) ON DELETE CASCADE,
+    clicked_at TIMESTAMPTZ NOT NULL,
+    referrer TEXT,
+    user_agent TEXT,
+    ip TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS clicks_url_id_idx ON clicks (url_id, clicked_at);
@@ -0,0 +1,7 @@
+-- only the hash of a key is stored, the key itself is shown once on creation
+CREATE TABLE IF NOT EXISTS api_keys (
+    id VARCHAR(32) PRIMARY KEY,
+    key_hash CHAR(64) NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,5 @@
+-- links created before this migration get the time it ran
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+-- the admin api pages through links by (created_at, id)
+CREATE INDEX IF NOT EXISTS urls_created_at_idx ON urls (created_at, id);
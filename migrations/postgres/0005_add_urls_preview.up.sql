@@ -0,0 +1,2 @@
+-- links that show a preview page instead of redirecting straight away
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS preview BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+-- default utm_* parameters added on redirect, as an encoded query string
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS utm TEXT;
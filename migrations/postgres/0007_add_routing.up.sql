@@ -0,0 +1,5 @@
+-- per-link redirect rules and split variants, as json
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS routing TEXT;
+
+-- the rule or split variant a click was sent to, NULL for the link's own url
+ALTER TABLE clicks ADD COLUMN IF NOT EXISTS variant TEXT;
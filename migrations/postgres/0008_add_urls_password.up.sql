@@ -0,0 +1,2 @@
+-- argon2 hash (PHC string, salt included) of the password guarding the link
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS password_hash TEXT;
@@ -0,0 +1,26 @@
+-- endpoints notified of clicks: on one link, or on every link of the owner
+-- when link_id is NULL. The secret signs the deliveries, so it is kept as is
+CREATE TABLE IF NOT EXISTS webhooks (
+    id VARCHAR(32) PRIMARY KEY,
+    owner VARCHAR(32) NOT NULL,
+    link_id VARCHAR(32) REFERENCES urls (id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS webhooks_link_id_idx ON webhooks (link_id);
+CREATE INDEX IF NOT EXISTS webhooks_owner_idx ON webhooks (owner) WHERE link_id IS NULL;
+
+-- deliveries that failed every attempt, kept after their webhook is deleted
+CREATE TABLE IF NOT EXISTS webhook_dead_letters (
+    id BIGSERIAL PRIMARY KEY,
+    webhook_id VARCHAR(32) NOT NULL,
+    url TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    error TEXT NOT NULL,
+    failed_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS webhook_dead_letters_failed_at_idx ON webhook_dead_letters (failed_at);
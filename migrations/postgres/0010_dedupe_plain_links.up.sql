@@ -0,0 +1,6 @@
+-- only plain links are deduplicated by url: a link with its own redirect
+-- status, preview, utm parameters or routing always gets a fresh id
+DROP INDEX IF EXISTS urls_permanent_url_owner_key;
+CREATE UNIQUE INDEX urls_permanent_url_owner_key ON urls (url, owner)
+    WHERE expires_at IS NULL AND max_clicks IS NULL AND redirect IS NULL AND NOT preview
+        AND utm IS NULL AND routing IS NULL;
@@ -0,0 +1,5 @@
+-- fails if a url has a plain link and a protected one for the same owner
+DROP INDEX IF EXISTS urls_permanent_url_owner_key;
+CREATE UNIQUE INDEX urls_permanent_url_owner_key ON urls (url, owner)
+    WHERE expires_at IS NULL AND max_clicks IS NULL AND redirect IS NULL AND NOT preview
+        AND utm IS NULL AND routing IS NULL;
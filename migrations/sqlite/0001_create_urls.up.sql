@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS urls (
+    id TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    expires_at TEXT,
+    max_clicks INTEGER,
+    clicks INTEGER NOT NULL DEFAULT 0,
+    owner TEXT NOT NULL DEFAULT '',
+    redirect INTEGER
+);
+
+-- only permanent links are deduplicated by url (per owner, '' being anonymous)
+CREATE UNIQUE INDEX IF NOT EXISTS urls_permanent_url_owner_key ON urls (url, owner)
+    WHERE expires_at IS NULL AND max_clicks IS NULL;
@@ -0,0 +1,7 @@
+-- sqlite can't add a column with a non-constant default, the store sets it on
+-- insert and links created before this migration get the time it ran
+ALTER TABLE urls ADD COLUMN created_at TEXT;
+UPDATE urls SET created_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now');
+
+-- the admin api pages through links by (created_at, id)
+CREATE INDEX urls_created_at_idx ON urls (created_at, id);
@@ -0,0 +1,4 @@
+-- fails if a url has a plain link and one with settings for the same owner
+DROP INDEX IF EXISTS urls_permanent_url_owner_key;
+CREATE UNIQUE INDEX urls_permanent_url_owner_key ON urls (url, owner)
+    WHERE expires_at IS NULL AND max_clicks IS NULL;
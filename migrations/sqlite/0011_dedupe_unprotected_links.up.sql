@@ -0,0 +1,5 @@
+-- a password guards a link of its own, protected links always get a fresh id
+DROP INDEX IF EXISTS urls_permanent_url_owner_key;
+CREATE UNIQUE INDEX urls_permanent_url_owner_key ON urls (url, owner)
+    WHERE expires_at IS NULL AND max_clicks IS NULL AND redirect IS NULL AND NOT preview
+        AND utm IS NULL AND routing IS NULL AND password_hash IS NULL;
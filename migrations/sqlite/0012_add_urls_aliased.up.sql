@@ -0,0 +1,7 @@
+-- links whose id was picked by their creator; an alias is a link of its own,
+-- also for a url the owner already shortened
+ALTER TABLE urls ADD COLUMN aliased BOOLEAN NOT NULL DEFAULT FALSE;
+DROP INDEX IF EXISTS urls_permanent_url_owner_key;
+CREATE UNIQUE INDEX urls_permanent_url_owner_key ON urls (url, owner)
+    WHERE expires_at IS NULL AND max_clicks IS NULL AND redirect IS NULL AND NOT preview
+        AND utm IS NULL AND routing IS NULL AND password_hash IS NULL AND NOT aliased;
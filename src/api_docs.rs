@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Json, Router,
+};
+use http::{header::CONTENT_TYPE, StatusCode};
+use serde_json::Value;
+use tracing::warn;
+use utoipa::openapi::OpenApi;
+use utoipa_swagger_ui::Config;
+
+// where the docs page finds the spec
+const SPEC_PATH: &str = "/openapi.json";
+
+#[derive(Clone)]
+struct Docs {
+    spec: Arc<OpenApi>,
+    swagger: Arc<Config<'static>>,
+}
+
+/// `/openapi.json` (the service's spec) and `/docs` (Swagger UI, bundled into
+/// the binary, rendering it), to merge into the service's router.
+pub fn routes<S>(spec: OpenApi) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let docs = Docs {
+        spec: Arc::new(spec),
+        swagger: Arc::new(Config::from(SPEC_PATH)),
+    };
+    Router::new()
+        .route(SPEC_PATH, get(openapi_json))
+        // the page loads its assets relative to `/docs/`
+        .route("/docs", get(|| async { Redirect::permanent("/docs/") }))
+        .route(
+            "/docs/",
+            get(|state| swagger_ui(Path(String::new()), state)),
+        )
+        .route("/docs/*file", get(swagger_ui))
+        .with_state(docs)
+}
+
+/// Test support: panics on fields of `value` that `schema` (part of `spec`,
+/// e.g. a response's) doesn't document, and on required ones it lacks. `at`
+/// says where the value came from in the messages.
+pub fn assert_documented(spec: &Value, schema: &Value, value: &Value, at: &str) {
+    let schema = deref(spec, schema);
+    if let (Some(items), Some(values)) = (schema.get("items"), value.as_array()) {
+        for value in values {
+            assert_documented(spec, items, value, at);
+        }
+        return;
+    }
+    let (Some(properties), Some(object)) = (schema["properties"].as_object(), value.as_object())
+    else {
+        return;
+    };
+    for (key, value) in object {
+        let property = properties
+            .get(key)
+            .unwrap_or_else(|| panic!("{at}: `{key}` isn't documented"));
+        assert_documented(spec, property, value, &format!("{at}.{key}"));
+    }
+    for key in schema["required"].as_array().into_iter().flatten() {
+        let key = key.as_str().unwrap();
+        assert!(
+            object.contains_key(key),
+            "{at}: required `{key}` is missing"
+        );
+    }
+}
+
+/// The schema a `$ref` points at, or `schema` itself. Panics on a `$ref`
+/// pointing nowhere in `spec`.
+pub fn deref<'a>(spec: &'a Value, schema: &'a Value) -> &'a Value {
+    match schema["$ref"].as_str() {
+        Some(pointer) => spec
+            .pointer(pointer.trim_start_matches('#'))
+            .unwrap_or_else(|| panic!("{pointer} points nowhere")),
+        None => schema,
+    }
+}
+
+async fn openapi_json(State(docs): State<Docs>) -> Json<OpenApi> {
+    Json(docs.spec.as_ref().clone())
+}
+
+async fn swagger_ui(Path(file): Path<String>, State(docs): State<Docs>) -> Response {
+    match utoipa_swagger_ui::serve(&file, docs.swagger.clone()) {
+        Ok(Some(file)) => {
+            ([(CONTENT_TYPE, file.content_type)], file.bytes.to_vec()).into_response()
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            warn!("Failed to serve swagger ui file {}: {}", file, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
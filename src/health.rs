@@ -0,0 +1,424 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use dashmap::DashMap;
+use futures::future::join_all;
+use http::{header::CONTENT_TYPE, HeaderValue, Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, Database, Pool};
+use tracing::warn;
+use utoipa::{OpenApi, ToSchema};
+
+// upper bounds of the latency histogram, in seconds, as the Prometheus clients default to
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+// a dependency slower than this to answer isn't ready either
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+// routes of requests no route matched, keeps unknown paths out of the labels
+const UNMATCHED: &str = "unmatched";
+// methods outside of RFC 9110 (and PATCH), clients can make up any number of them
+const OTHER_METHOD: &str = "other";
+const PROMETHEUS_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// A dependency a service can't serve requests without.
+#[async_trait]
+pub trait Probe: Send + Sync {
+    /// Fails if the dependency can't be used right now.
+    async fn check(&self) -> Result<()>;
+
+    /// Connection pool usage, for dependencies behind a pool.
+    fn pool(&self) -> Option<PoolStats> {
+        None
+    }
+}
+
+/// Connections of a pool, as `/metrics` reports them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: u32,
+    pub max: u32,
+}
+
+/// Body of `/readyz`: the outcome of every probe by name, `ok` or `failing`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: BTreeMap<String, String>,
+}
+
+/// `/healthz`, `/readyz` and `/metrics` as an OpenAPI document, to merge into
+/// the service's own.
+#[derive(OpenApi)]
+#[openapi(
+    paths(healthz, readyz, metrics),
+    tags((name = "health", description = "Probes and metrics of the service"))
+)]
+pub struct HealthApi;
+
+/// Request counts and latencies by method, route and status, and requests in
+/// flight. Cloning shares the counters.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    series: Arc<DashMap<SeriesKey, Series>>,
+    in_flight: Arc<AtomicI64>,
+}
+
+/// The probes of a service and its metrics, served by [`Health::routes`].
+#[derive(Clone, Default)]
+pub struct Health {
+    probes: Vec<(String, Arc<dyn Probe>)>,
+    metrics: Metrics,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct SeriesKey {
+    method: String,
+    route: String,
+    status: u16,
+}
+
+#[derive(Debug, Default)]
+struct Series {
+    // per bucket, cumulated when rendered; the last one is `+Inf`
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    micros: AtomicU64,
+}
+
+// keeps the in flight gauge right when a request is dropped halfway
+struct InFlight(Arc<AtomicI64>);
+
+#[async_trait]
+impl<DB: Database> Probe for Pool<DB> {
+    async fn check(&self) -> Result<()> {
+        self.acquire().await?.ping().await?;
+        Ok(())
+    }
+
+    fn pool(&self) -> Option<PoolStats> {
+        Some(PoolStats::of(self))
+    }
+}
+
+impl PoolStats {
+    pub fn of<DB: Database>(pool: &Pool<DB>) -> Self {
+        Self {
+            size: pool.size(),
+            idle: pool.num_idle() as u32,
+            max: pool.options().get_max_connections(),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn record(&self, method: &Method, route: &str, status: StatusCode, elapsed: Duration) {
+        let method = match *method {
+            Method::GET
+            | Method::HEAD
+            | Method::POST
+            | Method::PUT
+            | Method::DELETE
+            | Method::CONNECT
+            | Method::OPTIONS
+            | Method::TRACE
+            | Method::PATCH => method.as_str(),
+            _ => OTHER_METHOD,
+        };
+        let key = SeriesKey {
+            method: method.to_string(),
+            route: route.to_string(),
+            status: status.as_u16(),
+        };
+        let series = self.series.entry(key).or_default();
+        let secs = elapsed.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|le| secs <= *le)
+            .unwrap_or(BUCKETS.len());
+        series.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        series
+            .micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn in_flight(&self) -> i64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// The Prometheus text exposition of the metrics, and of the pools given.
+    pub fn render(&self, pools: &[(&str, PoolStats)]) -> String {
+        let mut series: Vec<_> = self
+            .series
+            .iter()
+            .map(|entry| {
+                let buckets: Vec<_> = entry
+                    .buckets
+                    .iter()
+                    .map(|n| n.load(Ordering::Relaxed))
+                    .collect();
+                let micros = entry.micros.load(Ordering::Relaxed);
+                (entry.key().clone(), buckets, micros)
+            })
+            .collect();
+        series.sort_by(|a, b| a.0.cmp(&b.0));
+
+        // writing to a String can't fail
+        let mut out = String::new();
+        header(
+            &mut out,
+            "http_requests_total",
+            "counter",
+            "Requests handled, by method, route and status.",
+        );
+        for (key, buckets, _) in &series {
+            let count: u64 = buckets.iter().sum();
+            let _ = writeln!(out, "http_requests_total{{{key}}} {count}");
+        }
+        header(
+            &mut out,
+            "http_request_duration_seconds",
+            "histogram",
+            "Time to respond, by method, route and status.",
+        );
+        for (key, buckets, micros) in &series {
+            let mut count = 0;
+            for (le, n) in BUCKETS.iter().map(|le| le.to_string()).zip(buckets) {
+                count += n;
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{key},le=\"{le}\"}} {count}"
+                );
+            }
+            count += buckets[BUCKETS.len()];
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{key},le=\"+Inf\"}} {count}"
+            );
+            let sum = *micros as f64 / 1e6;
+            let _ = writeln!(out, "http_request_duration_seconds_sum{{{key}}} {sum}");
+            let _ = writeln!(out, "http_request_duration_seconds_count{{{key}}} {count}");
+        }
+        header(
+            &mut out,
+            "http_requests_in_flight",
+            "gauge",
+            "Requests being handled.",
+        );
+        let _ = writeln!(out, "http_requests_in_flight {}", self.in_flight());
+        if !pools.is_empty() {
+            header(
+                &mut out,
+                "db_pool_connections",
+                "gauge",
+                "Open connections of a pool, by state.",
+            );
+            for (name, stats) in pools {
+                let name = escape(name);
+                let busy = stats.size.saturating_sub(stats.idle);
+                let _ = writeln!(
+                    out,
+                    "db_pool_connections{{pool=\"{name}\",state=\"idle\"}} {}",
+                    stats.idle
+                );
+                let _ = writeln!(
+                    out,
+                    "db_pool_connections{{pool=\"{name}\",state=\"busy\"}} {busy}"
+                );
+            }
+            header(
+                &mut out,
+                "db_pool_max_connections",
+                "gauge",
+                "Connections a pool opens at most.",
+            );
+            for (name, stats) in pools {
+                let _ = writeln!(
+                    out,
+                    "db_pool_max_connections{{pool=\"{}\"}} {}",
+                    escape(name),
+                    stats.max
+                );
+            }
+        }
+        out
+    }
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a dependency `/readyz` checks and, if it has a pool, `/metrics` reports.
+    pub fn with_probe(mut self, name: &str, probe: impl Probe + 'static) -> Self {
+        self.probes.push((name.to_string(), Arc::new(probe)));
+        self
+    }
+
+    /// The metrics to record requests into with [`track_metrics`].
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    /// `/healthz` (the process is up), `/readyz` (and so are its dependencies)
+    /// and `/metrics`, to merge into the service's router.
+    pub fn routes<S>(&self) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        Router::new()
+            .route("/healthz", get(healthz))
+            .route("/readyz", get(readyz))
+            .route("/metrics", get(metrics))
+            .with_state(self.clone())
+    }
+
+    /// Runs every probe at once, each given `PROBE_TIMEOUT` to answer.
+    pub async fn readiness(&self) -> Readiness {
+        let checks = self.probes.iter().map(|(name, probe)| async move {
+            let ret = match tokio::time::timeout(PROBE_TIMEOUT, probe.check()).await {
+                Ok(ret) => ret,
+                Err(_) => Err(anyhow::anyhow!("timed out after {:?}", PROBE_TIMEOUT)),
+            };
+            // the reason is logged, not handed to whoever asks
+            let outcome = match ret {
+                Ok(()) => "ok",
+                Err(e) => {
+                    warn!("Readiness probe {} failed: {:#}", name, e);
+                    "failing"
+                }
+            };
+            (name.clone(), outcome.to_string())
+        });
+        let checks: BTreeMap<_, _> = join_all(checks).await.into_iter().collect();
+        Readiness {
+            ready: checks.values().all(|outcome| outcome == "ok"),
+            checks,
+        }
+    }
+}
+
+// a probe is often a pool or a store, neither of them worth printing
+impl fmt::Debug for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let probes: Vec<_> = self.probes.iter().map(|(name, _)| name).collect();
+        f.debug_struct("Health")
+            .field("probes", &probes)
+            .field("metrics", &self.metrics)
+            .finish()
+    }
+}
+
+impl fmt::Display for SeriesKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "method=\"{}\",route=\"{}\",status=\"{}\"",
+            escape(&self.method),
+            escape(&self.route),
+            self.status
+        )
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Middleware for `axum::middleware::from_fn_with_state`, recording every
+/// request by the route it matched. Add it with `Router::layer` last, so the
+/// route is known and the time spent in other middleware counts.
+pub async fn track_metrics(State(metrics): State<Metrics>, req: Request, next: Next) -> Response {
+    let method = req.method().clone();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED, MatchedPath::as_str)
+        .to_string();
+    metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+    let _in_flight = InFlight(metrics.in_flight.clone());
+    let start = Instant::now();
+    let res = next.run(req).await;
+    metrics.record(&method, &route, res.status(), start.elapsed());
+    res
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses((status = 200, description = "The process is up", body = String, content_type = "text/plain"))
+)]
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "Every dependency can be used", body = Readiness),
+        (status = 503, description = "A dependency is failing or too slow to answer", body = Readiness),
+    )
+)]
+async fn readyz(State(health): State<Health>) -> impl IntoResponse {
+    let readiness = health.readiness().await;
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "health",
+    responses((status = 200, description = "Prometheus text exposition", body = String, content_type = "text/plain; version=0.0.4"))
+)]
+async fn metrics(State(health): State<Health>) -> impl IntoResponse {
+    let pools: Vec<_> = health
+        .probes
+        .iter()
+        .filter_map(|(name, probe)| Some((name.as_str(), probe.pool()?)))
+        .collect();
+    let body = health.metrics.render(&pools);
+    (
+        [(CONTENT_TYPE, HeaderValue::from_static(PROMETHEUS_TEXT))],
+        body,
+    )
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+// label values escape backslashes, quotes and newlines
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
@@ -1 +1,5 @@
-// empty file
+pub mod api_docs;
+pub mod health;
+pub mod rate_limit;
+pub mod shortener;
+pub mod shutdown;
@@ -0,0 +1,255 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use anyhow::{ensure, Result};
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dashmap::DashMap;
+use http::{
+    header::{AUTHORIZATION, RETRY_AFTER},
+    HeaderMap, HeaderName, HeaderValue, StatusCode,
+};
+use tracing::warn;
+
+const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+/// `burst` requests at once, refilled evenly over `period`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    burst: u32,
+    period: Duration,
+}
+
+/// Who a request is counted against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClientKey {
+    /// the id of a known api key
+    ApiKey(String),
+    Ip(IpAddr),
+}
+
+/// The api keys a service issued. Only keys it knows get a bucket of their
+/// own, anything else a client sends as a bearer could be made up.
+#[async_trait]
+pub trait ApiKeys: fmt::Debug + Send + Sync {
+    /// The id of the key, `None` if it was never issued.
+    async fn find(&self, key: &str) -> Result<Option<String>>;
+}
+
+/// Outcome of counting one request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// until the bucket is full again
+    pub reset: Duration,
+    /// until the next request would be let through, zero when allowed
+    pub retry_after: Duration,
+}
+
+/// Token buckets per client, one limiter per route (or group of routes) that
+/// shares a quota. Cloning shares the buckets.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    quota: Quota,
+    buckets: Arc<DashMap<ClientKey, Bucket>>,
+    // without them every client is counted by ip
+    api_keys: Option<Arc<dyn ApiKeys>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Quota {
+    /// Fails unless both `burst` and `period` are above zero, a bucket that is
+    /// never refilled can't tell anyone when to retry.
+    pub fn new(burst: u32, period: Duration) -> Result<Self> {
+        ensure!(burst > 0, "quota burst must be at least 1");
+        ensure!(!period.is_zero(), "quota period must be longer than zero");
+        Ok(Self { burst, period })
+    }
+
+    /// # Panics
+    ///
+    /// If `burst` is zero.
+    pub fn per_second(burst: u32) -> Self {
+        Self::new(burst, Duration::from_secs(1)).unwrap()
+    }
+
+    /// # Panics
+    ///
+    /// If `burst` is zero.
+    pub fn per_minute(burst: u32) -> Self {
+        Self::new(burst, Duration::from_secs(60)).unwrap()
+    }
+
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    // tokens refilled per second
+    fn rate(&self) -> f64 {
+        self.burst as f64 / self.period.as_secs_f64()
+    }
+}
+
+impl ClientKey {
+    /// The peer address of the request. Forwarded headers are ignored on
+    /// purpose, clients can set them to anything.
+    pub fn peer(req: &Request) -> Self {
+        // without ConnectInfo every client ends up in the same bucket
+        let ip = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        Self::Ip(ip)
+    }
+}
+
+impl RateLimiter {
+    /// Must be called within a tokio runtime: idle buckets are evicted in the
+    /// background until the limiter is dropped.
+    pub fn new(quota: Quota) -> Self {
+        let buckets = Arc::new(DashMap::new());
+        tokio::spawn(evict_idle(Arc::downgrade(&buckets), quota.period));
+        Self {
+            quota,
+            buckets,
+            api_keys: None,
+        }
+    }
+
+    /// Counts clients sending a known api key as a bearer by their key.
+    pub fn with_api_keys(mut self, api_keys: impl ApiKeys + 'static) -> Self {
+        self.api_keys = Some(Arc::new(api_keys));
+        self
+    }
+
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// The api key of a request if it is a known one, its `peer` otherwise.
+    /// Unknown keys fall back to the peer so making up a new key for every
+    /// request doesn't get a client a new bucket each time.
+    pub async fn client_key(&self, headers: &HeaderMap, peer: ClientKey) -> ClientKey {
+        let (Some(api_keys), Some(key)) = (&self.api_keys, bearer_key(headers)) else {
+            return peer;
+        };
+        match api_keys.find(key).await {
+            Ok(Some(id)) => ClientKey::ApiKey(id),
+            Ok(None) => peer,
+            Err(e) => {
+                warn!("Failed to look up api key, counting by ip: {:?}", e);
+                peer
+            }
+        }
+    }
+
+    /// Takes a token from the client's bucket, if there is one left.
+    pub fn check(&self, key: ClientKey) -> Decision {
+        let now = Instant::now();
+        let burst = self.quota.burst as f64;
+        let rate = self.quota.rate();
+        let mut bucket = self.buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        let retry_after = if allowed {
+            bucket.tokens -= 1.0;
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - bucket.tokens) / rate)
+        };
+        Decision {
+            allowed,
+            limit: self.quota.burst,
+            remaining: bucket.tokens as u32,
+            reset: Duration::from_secs_f64((burst - bucket.tokens) / rate),
+            retry_after,
+        }
+    }
+}
+
+impl Decision {
+    // with nested limiters the innermost (route specific) one gets to report
+    fn add_headers(&self, headers: &mut HeaderMap) {
+        let values: [(HeaderName, HeaderValue); 3] = [
+            (RATELIMIT_LIMIT, self.limit.into()),
+            (RATELIMIT_REMAINING, self.remaining.into()),
+            (RATELIMIT_RESET, ceil_secs(self.reset).into()),
+        ];
+        for (name, value) in values {
+            headers.entry(name).or_insert(value);
+        }
+    }
+}
+
+/// Middleware for `axum::middleware::from_fn_with_state`, rejecting clients
+/// over their quota with `429 Too Many Requests`.
+pub async fn rate_limit(State(limiter): State<RateLimiter>, req: Request, next: Next) -> Response {
+    let key = limiter
+        .client_key(req.headers(), ClientKey::peer(&req))
+        .await;
+    let decision = limiter.check(key);
+    if !decision.allowed {
+        let retry_after = ceil_secs(decision.retry_after).max(1);
+        let mut res = (StatusCode::TOO_MANY_REQUESTS, "too many requests").into_response();
+        decision.add_headers(res.headers_mut());
+        res.headers_mut().insert(RETRY_AFTER, retry_after.into());
+        return res;
+    }
+    let mut res = next.run(req).await;
+    decision.add_headers(res.headers_mut());
+    res
+}
+
+fn bearer_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs_f64().ceil() as u64
+}
+
+// a bucket untouched for a whole period is full again, forgetting it changes nothing
+async fn evict_idle(buckets: Weak<DashMap<ClientKey, Bucket>>, period: Duration) {
+    let mut interval = tokio::time::interval(period.max(Duration::from_secs(1)));
+    loop {
+        interval.tick().await;
+        let Some(buckets) = buckets.upgrade() else {
+            break;
+        };
+        let now = Instant::now();
+        buckets.retain(|_, bucket| now.saturating_duration_since(bucket.updated) < period);
+    }
+}
@@ -0,0 +1,214 @@
+use axum::{extract::State, Json};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{Admin, AppError, AppQuery, AppState, ProblemDetails, RedirectStatus};
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+
+/// A stored link as listed by the admin api.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct LinkSummary {
+    pub id: String,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_clicks: Option<i32>,
+    pub owner: Option<String>,
+    pub redirect: Option<RedirectStatus>,
+    pub preview: bool,
+    pub utm: Option<String>,
+    pub routing: Option<String>,
+    /// whether a password guards the link
+    pub protected: bool,
+    /// recorded visits, cached redirects included
+    pub clicks: i64,
+    /// whether the link ran out of `max_clicks`
+    pub used_up: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkSort {
+    #[default]
+    CreatedAt,
+    /// pages may skip or repeat links that are clicked while paging
+    Clicks,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Where the previous page ended: the sort keys of its last link.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkCursor {
+    pub created_at: DateTime<Utc>,
+    pub clicks: i64,
+    pub id: String,
+}
+
+/// A page of links to fetch from the store.
+#[derive(Debug, Clone, Default)]
+pub struct LinkQuery {
+    /// case insensitive substring of the target url
+    pub search: Option<String>,
+    /// lowercased host the target url must be on, subdomains included
+    pub domain: Option<String>,
+    pub sort: LinkSort,
+    pub order: SortOrder,
+    /// only links sorted after this one
+    pub after: Option<LinkCursor>,
+    pub limit: u32,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListLinksReq {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub q: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(default)]
+    #[param(inline)]
+    pub sort: LinkSort,
+    #[serde(default)]
+    #[param(inline)]
+    pub order: SortOrder,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    /// `next_cursor` of the previous page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ListLinksRes {
+    pub links: Vec<LinkSummary>,
+    /// missing on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// Pages through every stored link
+#[utoipa::path(
+    get,
+    path = "/admin/links",
+    tag = "admin",
+    params(ListLinksReq),
+    responses(
+        (status = 200, description = "A page of links", body = ListLinksRes),
+        (status = 400, description = "Malformed query or cursor", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or wrong admin token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "The admin api is disabled", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Limit out of range", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("admin_token" = []))
+)]
+pub(super) async fn list_links(
+    State(state): State<AppState>,
+    _: Admin,
+    AppQuery(req): AppQuery<ListLinksReq>,
+) -> Result<Json<ListLinksRes>, AppError> {
+    let limit = req.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::Validation(format!(
+            "limit must be between 1 and {MAX_PAGE_SIZE}"
+        )));
+    }
+    let after = req
+        .cursor
+        .as_deref()
+        .map(|cursor| {
+            LinkCursor::decode(cursor).ok_or_else(|| AppError::BadRequest("invalid cursor".into()))
+        })
+        .transpose()?;
+    let query = LinkQuery {
+        search: non_empty(req.q.as_deref()).map(str::to_string),
+        domain: non_empty(req.domain.as_deref().map(|d| d.trim_matches('.')))
+            .map(str::to_ascii_lowercase),
+        sort: req.sort,
+        order: req.order,
+        after,
+        // one more than asked for, to know whether there is a next page
+        limit: limit + 1,
+    };
+    let mut links = state
+        .store
+        .list(&query)
+        .await
+        .map_err(AppError::store("failed to list links"))?;
+    let next_cursor = if links.len() > limit as usize {
+        links.truncate(limit as usize);
+        links.last().map(|link| LinkCursor::from(link).encode())
+    } else {
+        None
+    };
+    Ok(Json(ListLinksRes { links, next_cursor }))
+}
+
+impl LinkSummary {
+    /// Expired and used up links are gone, like their redirects.
+    pub fn is_gone(&self) -> bool {
+        self.used_up || matches!(self.expires_at, Some(expires_at) if expires_at <= Utc::now())
+    }
+}
+
+impl LinkSort {
+    pub(crate) fn column(self) -> &'static str {
+        match self {
+            Self::CreatedAt => "created_at",
+            Self::Clicks => "clicks",
+        }
+    }
+}
+
+impl SortOrder {
+    pub(crate) fn keyword(self) -> &'static str {
+        match self {
+            Self::Asc => "ASC",
+            Self::Desc => "DESC",
+        }
+    }
+
+    /// How a row after the cursor compares to it.
+    pub(crate) fn after(self) -> &'static str {
+        match self {
+            Self::Asc => ">",
+            Self::Desc => "<",
+        }
+    }
+}
+
+impl LinkCursor {
+    /// Opaque to clients, the url safe base64 of the json.
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let json = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+impl From<&LinkSummary> for LinkCursor {
+    fn from(link: &LinkSummary) -> Self {
+        Self {
+            created_at: link.created_at,
+            clicks: link.clicks,
+            id: link.id.clone(),
+        }
+    }
+}
+
+fn non_empty(s: Option<&str>) -> Option<&str> {
+    s.map(str::trim).filter(|s| !s.is_empty())
+}
@@ -0,0 +1,174 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{async_trait, extract::FromRequestParts};
+use chrono::{DateTime, Utc};
+use http::{header::AUTHORIZATION, request::Parts};
+use moka::sync::Cache;
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{AppError, AppState, StoreError, UrlStore};
+use crate::rate_limit::ApiKeys;
+
+const KEY_PREFIX: &str = "sk_";
+// keys are never revoked, unknown ones are only forgotten so the cache can't
+// be flooded with made up keys for long
+const KEY_CACHE_CAPACITY: u64 = 10_000;
+const KEY_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// An issued api key. Only the blake3 hash of the key is ever stored.
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    pub id: String,
+    pub key_hash: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateKeyReq {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateKeyRes {
+    pub id: String,
+    /// shown only once, it can't be recovered later
+    pub key: String,
+}
+
+/// The api key (by id) a request was authenticated with, required.
+#[derive(Debug, Clone)]
+pub struct Owner(pub String);
+
+/// A request authenticated with the admin token.
+#[derive(Debug, Clone, Copy)]
+pub struct Admin;
+
+/// Like [`Owner`], but anonymous requests are let through. A key that is
+/// present but unknown is still rejected.
+#[derive(Debug, Clone)]
+pub struct MaybeOwner(pub Option<String>);
+
+/// Api key lookups by key, shared by the rate limiters and the extractors so a
+/// request sending a key costs at most one query.
+#[derive(Debug, Clone)]
+pub(crate) struct ApiKeyCache {
+    store: Arc<dyn UrlStore>,
+    keys: Cache<String, Option<String>>,
+}
+
+impl ApiKey {
+    /// Generates a new key, returning it along with the plaintext to hand out.
+    pub fn generate(name: impl Into<String>) -> (Self, String) {
+        let key = format!("{}{}", KEY_PREFIX, nanoid!(32));
+        let api_key = Self {
+            id: nanoid!(8),
+            key_hash: hash_key(&key),
+            name: name.into(),
+            created_at: Utc::now(),
+        };
+        (api_key, key)
+    }
+}
+
+pub fn hash_key(key: &str) -> String {
+    blake3::hash(key.as_bytes()).to_hex().to_string()
+}
+
+impl ApiKeyCache {
+    pub(crate) fn new(store: Arc<dyn UrlStore>) -> Self {
+        let keys = Cache::builder()
+            .max_capacity(KEY_CACHE_CAPACITY)
+            .time_to_live(KEY_CACHE_TTL)
+            .build();
+        Self { store, keys }
+    }
+
+    /// The id of the key, `None` if it was never issued.
+    pub(crate) async fn find(&self, key: &str) -> Result<Option<String>, StoreError> {
+        let key_hash = hash_key(key);
+        if let Some(id) = self.keys.get(&key_hash) {
+            return Ok(id);
+        }
+        let id = self.store.find_api_key(&key_hash).await?;
+        self.keys.insert(key_hash, id.clone());
+        Ok(id)
+    }
+}
+
+// only known api keys get a rate limit bucket of their own
+#[async_trait]
+impl ApiKeys for ApiKeyCache {
+    async fn find(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(ApiKeyCache::find(self, key).await?)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for MaybeOwner {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(AUTHORIZATION) else {
+            return Ok(Self(None));
+        };
+        let unauthorized = || AppError::Unauthorized("invalid api key".to_string());
+        let key = value
+            .to_str()
+            .ok()
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or_else(unauthorized)?;
+        let owner = state
+            .api_keys
+            .find(key.trim())
+            .await
+            .map_err(AppError::store("failed to look up api key"))?
+            .ok_or_else(unauthorized)?;
+        Ok(Self(Some(owner)))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Owner {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        match MaybeOwner::from_request_parts(parts, state).await? {
+            MaybeOwner(Some(owner)) => Ok(Self(owner)),
+            MaybeOwner(None) => Err(AppError::Unauthorized("missing api key".to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Admin {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(admin_token) = state.admin_token else {
+            return Err(AppError::Forbidden("admin api is disabled".to_string()));
+        };
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or_else(|| AppError::Unauthorized("missing admin token".to_string()))?;
+        // blake3::Hash compares in constant time
+        if blake3::hash(token.trim().as_bytes()) != admin_token {
+            return Err(AppError::Unauthorized("invalid admin token".to_string()));
+        }
+        Ok(Self)
+    }
+}
@@ -0,0 +1,343 @@
+use axum::{
+    extract::{FromRequest, Multipart, Request, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use http::{
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::{
+    Admin, AppError, AppQuery, AppState, LinkRecord, MaybeOwner, ProblemDetails, RedirectStatus,
+    ShortenReq, UtmParams,
+};
+
+const MAX_BULK_ROWS: usize = 1000;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BulkRes {
+    pub created: usize,
+    pub failed: usize,
+    pub results: Vec<BulkItem>,
+}
+
+/// Outcome of one row of a bulk request, rows fail independently.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BulkItem {
+    /// position of the row in the request, csv header excluded
+    pub index: usize,
+    pub url: String,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub short_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct ExportParams {
+    #[serde(default)]
+    #[param(inline)]
+    format: ExportFormat,
+}
+
+/// A row of the export: the stored link, its password hash left out.
+#[derive(Debug, Serialize)]
+struct ExportRow<'a> {
+    id: &'a str,
+    url: &'a str,
+    expires_at: Option<DateTime<Utc>>,
+    max_clicks: Option<i32>,
+    clicks: i32,
+    owner: Option<&'a str>,
+    redirect: Option<RedirectStatus>,
+    preview: bool,
+    utm: Option<&'a str>,
+    routing: Option<&'a str>,
+    protected: bool,
+}
+
+/// The multipart form `bulk_shorten` takes, as the api docs describe it.
+#[derive(ToSchema)]
+pub(super) struct CsvUpload {
+    /// `url,alias,expires_at,max_clicks` columns, only `url` is required. The
+    /// csv `/admin/export` writes is taken as well, its `id` being the alias.
+    #[schema(value_type = String, format = Binary, content_media_type = "text/csv")]
+    file: Vec<u8>,
+}
+
+/// A row of an uploaded csv: the fields of a [`ShortenReq`], `utm` and
+/// `routing` written as the export does, a query string and json.
+#[derive(Debug, Deserialize)]
+struct CsvRow {
+    url: String,
+    #[serde(alias = "id")]
+    alias: Option<String>,
+    expires_at: Option<DateTime<Utc>>,
+    max_clicks: Option<i32>,
+    redirect: Option<RedirectStatus>,
+    preview: Option<bool>,
+    utm: Option<String>,
+    routing: Option<String>,
+    password: Option<String>,
+    // exported links lose their password, they must not come back unprotected
+    protected: Option<bool>,
+}
+
+// a row that couldn't even be parsed into a request
+struct InvalidRow {
+    url: String,
+    error: String,
+}
+
+/// Shortens a JSON array of requests, or the rows of a csv uploaded as the
+/// `file` field of a multipart form (`url,alias,expires_at,max_clicks` columns,
+/// or those of `/admin/export`).
+#[utoipa::path(
+    post,
+    path = "/bulk",
+    tag = "links",
+    request_body(content(
+        (Vec<ShortenReq> = "application/json",
+            example = json!([{"url": "https://example.com/a"}, {"url": "https://example.com/b"}])),
+        (CsvUpload = "multipart/form-data"),
+    )),
+    responses(
+        (status = 200, description = "The outcome of every row", body = BulkRes),
+        (status = 400, description = "Malformed json or csv", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Unknown api key", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 413, description = "Too many rows", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Json that isn't an array of requests", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security((), ("api_key" = []))
+)]
+pub(super) async fn bulk_shorten(
+    State(state): State<AppState>,
+    MaybeOwner(owner): MaybeOwner,
+    req: Request,
+) -> Result<impl IntoResponse, AppError> {
+    let is_multipart = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("multipart/form-data"));
+    let rows = if is_multipart {
+        let multipart = Multipart::from_request(req, &state)
+            .await
+            .map_err(|e| AppError::BadRequest(e.body_text()))?;
+        CsvUpload::read(multipart).await?.rows()?
+    } else {
+        let Json(reqs) = Json::<Vec<ShortenReq>>::from_request(req, &state).await?;
+        reqs.into_iter().map(Ok).collect()
+    };
+    if rows.len() > MAX_BULK_ROWS {
+        return Err(AppError::PayloadTooLarge(format!(
+            "at most {} rows per request",
+            MAX_BULK_ROWS
+        )));
+    }
+
+    let mut results = Vec::with_capacity(rows.len());
+    for (index, row) in rows.into_iter().enumerate() {
+        let item = match row {
+            Ok(data) => match state.create_link(&data, owner.clone()).await {
+                Ok(id) => BulkItem {
+                    index,
+                    url: data.url,
+                    status: StatusCode::CREATED.as_u16(),
+                    short_url: Some(state.short_url(&id)),
+                    error: None,
+                },
+                Err(e) => {
+                    e.log();
+                    BulkItem {
+                        index,
+                        url: data.url,
+                        status: e.status().as_u16(),
+                        short_url: None,
+                        error: Some(e.to_string()),
+                    }
+                }
+            },
+            Err(row) => BulkItem {
+                index,
+                url: row.url,
+                status: StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                short_url: None,
+                error: Some(row.error),
+            },
+        };
+        results.push(item);
+    }
+    let created = results.iter().filter(|r| r.short_url.is_some()).count();
+    Ok(Json(BulkRes {
+        created,
+        failed: results.len() - created,
+        results,
+    }))
+}
+
+/// Dumps every stored link as csv (default) or ndjson, for backups.
+#[utoipa::path(
+    get,
+    path = "/admin/export",
+    tag = "admin",
+    params(ExportParams),
+    responses(
+        (status = 200, description = "Every stored link, as a file to download",
+            content(
+                (String = "text/csv"),
+                (String = "application/x-ndjson"),
+            )),
+        (status = 400, description = "Unknown format", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or wrong admin token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "The admin api is disabled", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("admin_token" = []))
+)]
+pub(super) async fn export(
+    State(state): State<AppState>,
+    _: Admin,
+    AppQuery(params): AppQuery<ExportParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let links = state
+        .store
+        .export()
+        .await
+        .map_err(AppError::store("failed to export links"))?;
+    let (content_type, ext, body) = match params.format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            for link in &links {
+                writer
+                    .serialize(ExportRow::from(link))
+                    .map_err(|e| AppError::internal("failed to write csv export", e))?;
+            }
+            let body = writer
+                .into_inner()
+                .map_err(|e| AppError::internal("failed to write csv export", e.into_error()))?;
+            ("text/csv", "csv", body)
+        }
+        ExportFormat::Ndjson => {
+            let mut body = vec![];
+            for link in &links {
+                serde_json::to_writer(&mut body, &ExportRow::from(link))
+                    .map_err(|e| AppError::internal("failed to write ndjson export", e))?;
+                body.push(b'\n');
+            }
+            ("application/x-ndjson", "ndjson", body)
+        }
+    };
+    let disposition = format!("attachment; filename=\"links.{}\"", ext);
+    Ok((
+        [
+            (CONTENT_TYPE, content_type.to_string()),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    ))
+}
+
+impl<'a> From<&'a LinkRecord> for ExportRow<'a> {
+    fn from(link: &'a LinkRecord) -> Self {
+        Self {
+            id: &link.id,
+            url: &link.url,
+            expires_at: link.expires_at,
+            max_clicks: link.max_clicks,
+            clicks: link.clicks,
+            owner: link.owner.as_deref(),
+            redirect: link.redirect,
+            preview: link.preview,
+            utm: link.utm.as_deref(),
+            routing: link.routing.as_deref(),
+            protected: link.password_hash.is_some(),
+        }
+    }
+}
+
+impl CsvUpload {
+    async fn read(mut multipart: Multipart) -> Result<Self, AppError> {
+        while let Some(field) = multipart.next_field().await? {
+            if field.name() == Some("file") {
+                let file = field.bytes().await?.to_vec();
+                return Ok(Self { file });
+            }
+        }
+        Err(AppError::BadRequest(
+            "multipart form must have a file field".to_string(),
+        ))
+    }
+
+    fn rows(&self) -> Result<Vec<Result<ShortenReq, InvalidRow>>, AppError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(self.file.as_slice());
+        let headers = reader
+            .headers()
+            .map_err(|e| AppError::BadRequest(format!("invalid csv header: {e}")))?
+            .clone();
+        let url_column = headers
+            .iter()
+            .position(|h| h == "url")
+            .ok_or_else(|| AppError::BadRequest("csv must have a url column".to_string()))?;
+        let rows = reader
+            .records()
+            .map(|record| {
+                let record = record.map_err(|e| InvalidRow {
+                    url: String::new(),
+                    error: e.to_string(),
+                })?;
+                let invalid = |error: String| InvalidRow {
+                    url: record.get(url_column).unwrap_or_default().to_string(),
+                    error,
+                };
+                let row: CsvRow = record
+                    .deserialize(Some(&headers))
+                    .map_err(|e| invalid(e.to_string()))?;
+                row.into_req().map_err(invalid)
+            })
+            .collect();
+        Ok(rows)
+    }
+}
+
+impl CsvRow {
+    fn into_req(self) -> Result<ShortenReq, String> {
+        if self.protected == Some(true) && self.password.is_none() {
+            return Err(
+                "protected links need their password, the export leaves it out".to_string(),
+            );
+        }
+        let routing = self
+            .routing
+            .map(|routing| serde_json::from_str(&routing))
+            .transpose()
+            .map_err(|e| format!("invalid routing: {e}"))?;
+        Ok(ShortenReq {
+            url: self.url,
+            alias: self.alias,
+            expires_at: self.expires_at,
+            max_clicks: self.max_clicks,
+            redirect: self.redirect,
+            preview: self.preview.unwrap_or_default(),
+            utm: self.utm.map(|utm| UtmParams::from_query(&utm)),
+            routing,
+            password: self.password,
+            sealed: false,
+        })
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use moka::{sync::Cache, Expiry};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::RedirectStatus;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheConfig {
+    /// max number of cached ids
+    pub capacity: u64,
+    /// how long a resolved url is served from memory
+    pub ttl: Duration,
+    /// how long an unknown id is remembered as missing
+    pub negative_ttl: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: u64,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Cached {
+    Url {
+        url: String,
+        expires_at: Option<DateTime<Utc>>,
+        redirect: Option<RedirectStatus>,
+        preview: bool,
+        utm: Option<String>,
+        routing: Option<String>,
+        protected: bool,
+    },
+    Missing,
+}
+
+/// Bounded TinyLFU cache in front of the store's redirect lookups.
+#[derive(Debug, Clone)]
+pub(crate) struct RedirectCache {
+    cache: Cache<String, Cached>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+struct CacheExpiry {
+    ttl: Duration,
+    negative_ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            ttl: Duration::from_secs(300),
+            negative_ttl: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RedirectCache {
+    pub(crate) fn new(config: CacheConfig) -> Self {
+        let cache = Cache::builder()
+            .max_capacity(config.capacity)
+            .expire_after(CacheExpiry {
+                ttl: config.ttl,
+                negative_ttl: config.negative_ttl,
+            })
+            .build();
+        Self {
+            cache,
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Option<Cached> {
+        let ret = self.cache.get(id);
+        let counter = if ret.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        ret
+    }
+
+    pub(crate) fn insert(&self, id: &str, cached: Cached) {
+        self.cache.insert(id.to_string(), cached);
+    }
+
+    pub(crate) fn invalidate(&self, id: &str) {
+        self.cache.invalidate(id);
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.cache.entry_count(),
+        }
+    }
+}
+
+impl Expiry<String, Cached> for CacheExpiry {
+    fn expire_after_create(
+        &self,
+        _id: &String,
+        cached: &Cached,
+        _now: Instant,
+    ) -> Option<Duration> {
+        let ttl = match cached {
+            Cached::Missing => self.negative_ttl,
+            // never serve an expiring link from memory past its expiry
+            Cached::Url {
+                expires_at: Some(expires_at),
+                ..
+            } => (*expires_at - Utc::now())
+                .to_std()
+                .unwrap_or_default()
+                .min(self.ttl),
+            Cached::Url { .. } => self.ttl,
+        };
+        Some(ttl)
+    }
+}
@@ -0,0 +1,131 @@
+use http::StatusCode;
+use reqwest::{redirect::Policy, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+use super::{
+    BulkRes, ListLinksReq, ListLinksRes, ProblemDetails, Resolution, ShortenReq, ShortenRes,
+    StatsRes,
+};
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    /// the shortener turned the request down, with problem details
+    #[error("{} {}: {}", .0.status, .0.title, .0.detail)]
+    Api(ProblemDetails),
+    /// an error response without problem details, e.g. from the rate limiter
+    #[error("shortener responded with {0}")]
+    Status(StatusCode),
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+}
+
+/// A client of the shortener's api, speaking the server's own request and
+/// response types.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    admin_token: Option<String>,
+}
+
+impl Client {
+    /// A client of the shortener at `base_url`, e.g. `http://localhost:9876`.
+    pub fn new(base_url: &str) -> Result<Self, ClientError> {
+        // redirects are what the shortener answers with, never follow them
+        let http = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .build()?;
+        Ok(Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: None,
+            admin_token: None,
+        })
+    }
+
+    /// Owns the links it creates, and may delete them.
+    pub fn with_api_key(mut self, key: &str) -> Self {
+        self.api_key = Some(key.to_string());
+        self
+    }
+
+    /// Needed to list links.
+    pub fn with_admin_token(mut self, token: &str) -> Self {
+        self.admin_token = Some(token.to_string());
+        self
+    }
+
+    /// The id of a short url of this shortener, anything else is taken for an id.
+    pub fn id_of<'a>(&self, short_url: &'a str) -> &'a str {
+        short_url
+            .strip_prefix(self.base_url.as_str())
+            .map_or(short_url, |path| path.trim_start_matches('/'))
+    }
+
+    pub async fn shorten(&self, req: &ShortenReq) -> Result<ShortenRes, ClientError> {
+        let req = self.http.post(&self.base_url).json(req);
+        json(self.send(req, self.api_key.as_deref()).await?).await
+    }
+
+    /// Shortens up to the server's limit of requests at once, each succeeding
+    /// or failing on its own.
+    pub async fn bulk_shorten(&self, reqs: &[ShortenReq]) -> Result<BulkRes, ClientError> {
+        let req = self.http.post(self.url("bulk")).json(reqs);
+        json(self.send(req, self.api_key.as_deref()).await?).await
+    }
+
+    /// Where the short url points, without counting a visit.
+    pub async fn resolve(&self, id: &str) -> Result<Resolution, ClientError> {
+        let req = self.http.get(self.url(&format!("{id}/info")));
+        json(self.send(req, None).await?).await
+    }
+
+    pub async fn stats(&self, id: &str) -> Result<StatsRes, ClientError> {
+        let req = self.http.get(self.url(&format!("{id}/stats")));
+        json(self.send(req, None).await?).await
+    }
+
+    /// A page of every stored link, with the admin token.
+    pub async fn list_links(&self, req: &ListLinksReq) -> Result<ListLinksRes, ClientError> {
+        let req = self.http.get(self.url("admin/links")).query(req);
+        json(self.send(req, self.admin_token.as_deref()).await?).await
+    }
+
+    /// Deletes a link owned by the api key.
+    pub async fn delete(&self, id: &str) -> Result<(), ClientError> {
+        let req = self.http.delete(self.url(id));
+        self.send(req, self.api_key.as_deref()).await?;
+        Ok(())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path)
+    }
+
+    // sends the request, turning error statuses into errors
+    async fn send(
+        &self,
+        req: RequestBuilder,
+        token: Option<&str>,
+    ) -> Result<Response, ClientError> {
+        let req = match token {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        };
+        let res = req.send().await?;
+        let status = res.status();
+        if !(status.is_client_error() || status.is_server_error()) {
+            return Ok(res);
+        }
+        match res.json::<ProblemDetails>().await {
+            Ok(problem) => Err(ClientError::Api(problem)),
+            Err(_) => Err(ClientError::Status(status)),
+        }
+    }
+}
+
+async fn json<T: DeserializeOwned>(res: Response) -> Result<T, ClientError> {
+    Ok(res.json().await?)
+}
@@ -0,0 +1,186 @@
+use axum::{
+    extract::{
+        multipart::MultipartError,
+        rejection::{FormRejection, JsonRejection, QueryRejection},
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use http::{header::CONTENT_TYPE, HeaderValue, StatusCode};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{error, warn};
+use utoipa::ToSchema;
+
+use super::{StoreError, UrlError};
+
+/// Everything a shortener handler can fail with.
+#[derive(Error, Debug)]
+pub enum AppError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    /// the link exists but has expired or used up its clicks
+    #[error("{0}")]
+    Gone(String),
+    #[error("{0}")]
+    PayloadTooLarge(String),
+    #[error("{0}")]
+    Validation(String),
+    /// the store can't be reached right now, retrying may help
+    #[error("storage is unavailable")]
+    Unavailable {
+        context: String,
+        #[source]
+        source: StoreError,
+    },
+    #[error("internal error")]
+    Internal {
+        context: String,
+        #[source]
+        source: anyhow::Error,
+    },
+}
+
+/// RFC 9457 problem details, the body of every error response.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+}
+
+impl AppError {
+    /// Maps a store error, `context` says what was being done when it failed.
+    pub(crate) fn store(context: impl Into<String>) -> impl FnOnce(StoreError) -> Self {
+        move |e| match e {
+            StoreError::NotFound(_) | StoreError::WebhookNotFound(_) => {
+                Self::NotFound(e.to_string())
+            }
+            StoreError::NotOwner(_) => Self::Forbidden(e.to_string()),
+            StoreError::IdTaken(_) | StoreError::UrlTaken => Self::Conflict(e.to_string()),
+            StoreError::Database(ref db_err) if is_unavailable(db_err) => Self::Unavailable {
+                context: context.into(),
+                source: e,
+            },
+            _ => Self::Internal {
+                context: context.into(),
+                source: e.into(),
+            },
+        }
+    }
+
+    pub(crate) fn internal(context: impl Into<String>, source: impl Into<anyhow::Error>) -> Self {
+        Self::Internal {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::Gone(_) => StatusCode::GONE,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Logs the errors ops should hear about, client errors aren't.
+    pub(crate) fn log(&self) {
+        match self {
+            Self::Unavailable { context, source } => warn!("{}: {:?}", context, source),
+            Self::Internal { context, source } => error!("{}: {:?}", context, source),
+            _ => {}
+        }
+    }
+
+    pub fn problem(&self) -> ProblemDetails {
+        let status = self.status();
+        ProblemDetails {
+            kind: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail: self.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        self.log();
+        let mut res = (self.status(), Json(self.problem())).into_response();
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        res
+    }
+}
+
+impl From<UrlError> for AppError {
+    fn from(e: UrlError) -> Self {
+        Self::Validation(e.to_string())
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(e: JsonRejection) -> Self {
+        match e {
+            // well-formed json that doesn't match the request type
+            JsonRejection::JsonDataError(_) => Self::Validation(e.body_text()),
+            _ => Self::BadRequest(e.body_text()),
+        }
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(e: QueryRejection) -> Self {
+        Self::BadRequest(e.body_text())
+    }
+}
+
+impl From<FormRejection> for AppError {
+    fn from(e: FormRejection) -> Self {
+        match e {
+            FormRejection::FailedToDeserializeFormBody(_) => Self::Validation(e.body_text()),
+            _ => Self::BadRequest(e.body_text()),
+        }
+    }
+}
+
+impl From<MultipartError> for AppError {
+    fn from(e: MultipartError) -> Self {
+        match e.status() {
+            StatusCode::PAYLOAD_TOO_LARGE => Self::PayloadTooLarge(e.body_text()),
+            _ => Self::BadRequest(e.body_text()),
+        }
+    }
+}
+
+// errors that say nothing about the request, only about the database being reachable
+fn is_unavailable(e: &sqlx::Error) -> bool {
+    matches!(
+        e,
+        sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::WorkerCrashed
+    )
+}
@@ -0,0 +1,281 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use super::ALIAS_MAX_LEN;
+
+const BASE62: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Produces candidate ids for new links.
+pub trait IdGenerator: fmt::Debug + Send + Sync {
+    /// `attempt` starts at 0 and grows every time the previous candidate was taken,
+    /// deterministic generators must use it to come up with a different id.
+    fn generate(&self, url: &str, attempt: u32) -> String;
+}
+
+/// Which [`IdGenerator`] a configured server hands out ids with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum IdScheme {
+    /// [`NanoId`]
+    #[default]
+    Nanoid,
+    /// [`Sequence`], counting in memory from 0
+    Sequence,
+    /// [`ObfuscatedSequence`], counting in memory from 0
+    Obfuscated,
+    /// [`ContentHash`]
+    Hash,
+}
+
+/// Random ids, the default.
+#[derive(Debug, Clone)]
+pub struct NanoId {
+    length: usize,
+    alphabet: Vec<char>,
+}
+
+/// Base62 encoded sequence numbers: short and ordered, but easy to enumerate.
+#[derive(Debug)]
+pub struct Sequence {
+    next: AtomicU64,
+    min_length: usize,
+    alphabet: Vec<char>,
+}
+
+/// Sequence numbers scrambled with a salt (hashids-style), so consecutive links
+/// don't get guessable ids.
+#[derive(Debug)]
+pub struct ObfuscatedSequence {
+    next: AtomicU64,
+    length: usize,
+    // salt-shuffled alphabet
+    alphabet: Vec<char>,
+    // ids are `(n * multiplier + offset) mod 2^bits`, a bijection for an odd multiplier
+    bits: u32,
+    multiplier: u64,
+    offset: u64,
+}
+
+/// blake3 hash of the url: the same url always gets the same id.
+#[derive(Debug, Clone)]
+pub struct ContentHash {
+    length: usize,
+    alphabet: Vec<char>,
+}
+
+impl IdScheme {
+    /// Whether the generator counts in memory: after a restart it starts over
+    /// and hands out the ids of links created before, which only an empty
+    /// store can take.
+    pub fn counts_in_memory(self) -> bool {
+        matches!(self, Self::Sequence | Self::Obfuscated)
+    }
+
+    /// The generator with the given settings, the scheme's defaults for the
+    /// ones left out. Obfuscated sequences need a `salt`.
+    pub fn build(
+        self,
+        length: Option<usize>,
+        alphabet: Option<&str>,
+        salt: Option<&str>,
+    ) -> Result<Arc<dyn IdGenerator>> {
+        Ok(match self {
+            Self::Nanoid => {
+                let safe: String = nanoid::alphabet::SAFE.iter().collect();
+                Arc::new(NanoId::new(length.unwrap_or(6), alphabet.unwrap_or(&safe))?)
+            }
+            Self::Sequence => {
+                if alphabet.is_some() {
+                    bail!("sequence ids are always base62, they take no alphabet");
+                }
+                Arc::new(Sequence::new(0, length.unwrap_or(1))?)
+            }
+            Self::Obfuscated => {
+                let salt = salt.context("obfuscated sequence ids need a salt")?;
+                Arc::new(ObfuscatedSequence::new(
+                    0,
+                    length.unwrap_or(8),
+                    alphabet.unwrap_or(BASE62),
+                    salt,
+                )?)
+            }
+            Self::Hash => Arc::new(ContentHash::new(
+                length.unwrap_or(8),
+                alphabet.unwrap_or(BASE62),
+            )?),
+        })
+    }
+}
+
+impl Default for NanoId {
+    fn default() -> Self {
+        Self {
+            length: 6,
+            alphabet: nanoid::alphabet::SAFE.to_vec(),
+        }
+    }
+}
+
+impl NanoId {
+    pub fn new(length: usize, alphabet: &str) -> Result<Self> {
+        Ok(Self {
+            length: validate_length(length)?,
+            alphabet: validate_alphabet(alphabet)?,
+        })
+    }
+}
+
+impl IdGenerator for NanoId {
+    fn generate(&self, _url: &str, _attempt: u32) -> String {
+        nanoid::format(nanoid::rngs::default, &self.alphabet, self.length)
+    }
+}
+
+impl Sequence {
+    /// The counter lives in memory, start it past the ids already handed out.
+    pub fn new(start: u64, min_length: usize) -> Result<Self> {
+        Ok(Self {
+            next: AtomicU64::new(start),
+            min_length: validate_length(min_length)?,
+            alphabet: BASE62.chars().collect(),
+        })
+    }
+}
+
+impl IdGenerator for Sequence {
+    fn generate(&self, _url: &str, _attempt: u32) -> String {
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        encode(n, &self.alphabet, self.min_length)
+    }
+}
+
+impl ObfuscatedSequence {
+    pub fn new(start: u64, length: usize, alphabet: &str, salt: &str) -> Result<Self> {
+        let length = validate_length(length)?;
+        let mut alphabet = validate_alphabet(alphabet)?;
+        let mut salt = blake3::Hasher::new_derive_key("ecosystem shortener id salt")
+            .update(salt.as_bytes())
+            .finalize_xof();
+        let mut next_u64 = || {
+            let mut buf = [0u8; 8];
+            salt.fill(&mut buf);
+            u64::from_le_bytes(buf)
+        };
+        // Fisher-Yates, driven by the salt
+        for i in (1..alphabet.len()).rev() {
+            let j = (next_u64() % (i as u64 + 1)) as usize;
+            alphabet.swap(i, j);
+        }
+        // largest power of two that still fits into `length` chars
+        let bits = ((alphabet.len() as f64).log2() * length as f64)
+            .floor()
+            .min(63.0) as u32;
+        let mask = (1u64 << bits) - 1;
+        Ok(Self {
+            next: AtomicU64::new(start),
+            length,
+            alphabet,
+            bits,
+            multiplier: next_u64() | 1,
+            offset: next_u64() & mask,
+        })
+    }
+}
+
+impl IdGenerator for ObfuscatedSequence {
+    fn generate(&self, _url: &str, _attempt: u32) -> String {
+        let mask = (1u64 << self.bits) - 1;
+        let n = self.next.fetch_add(1, Ordering::Relaxed) & mask;
+        let n = n.wrapping_mul(self.multiplier).wrapping_add(self.offset) & mask;
+        encode(n, &self.alphabet, self.length)
+    }
+}
+
+impl ContentHash {
+    pub fn new(length: usize, alphabet: &str) -> Result<Self> {
+        Ok(Self {
+            length: validate_length(length)?,
+            alphabet: validate_alphabet(alphabet)?,
+        })
+    }
+}
+
+impl Default for ContentHash {
+    fn default() -> Self {
+        Self {
+            length: 8,
+            alphabet: BASE62.chars().collect(),
+        }
+    }
+}
+
+impl IdGenerator for ContentHash {
+    fn generate(&self, url: &str, attempt: u32) -> String {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(url.as_bytes());
+        if attempt > 0 {
+            hasher.update(&attempt.to_le_bytes());
+        }
+        let mut reader = hasher.finalize_xof();
+        let base = self.alphabet.len() as u64;
+        (0..self.length)
+            .map(|_| {
+                let mut buf = [0u8; 8];
+                reader.fill(&mut buf);
+                self.alphabet[(u64::from_le_bytes(buf) % base) as usize]
+            })
+            .collect()
+    }
+}
+
+fn encode(mut n: u64, alphabet: &[char], min_length: usize) -> String {
+    let base = alphabet.len() as u64;
+    let mut ret = Vec::new();
+    loop {
+        ret.push(alphabet[(n % base) as usize]);
+        n /= base;
+        if n == 0 {
+            break;
+        }
+    }
+    while ret.len() < min_length {
+        ret.push(alphabet[0]);
+    }
+    ret.iter().rev().collect()
+}
+
+fn validate_length(length: usize) -> Result<usize> {
+    if length == 0 || length > ALIAS_MAX_LEN {
+        bail!("id length must be between 1 and {}", ALIAS_MAX_LEN);
+    }
+    Ok(length)
+}
+
+fn validate_alphabet(alphabet: &str) -> Result<Vec<char>> {
+    let chars: Vec<char> = alphabet.chars().collect();
+    if chars.len() < 2 {
+        bail!("id alphabet needs at least 2 characters");
+    }
+    // ids end up in the url path, keep them to the alias charset
+    if let Some(c) = chars
+        .iter()
+        .find(|c| !c.is_ascii_alphanumeric() && **c != '-' && **c != '_')
+    {
+        bail!("id alphabet can't contain {:?}", c);
+    }
+    let mut sorted = chars.clone();
+    sorted.sort_unstable();
+    sorted.dedup();
+    if sorted.len() != chars.len() {
+        bail!("id alphabet contains duplicate characters");
+    }
+    Ok(chars)
+}
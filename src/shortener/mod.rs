@@ -0,0 +1,1242 @@
+mod admin;
+mod auth;
+mod bulk;
+mod cache;
+mod client;
+mod config;
+mod error;
+mod id;
+mod openapi;
+mod preview;
+mod query;
+mod routing;
+mod sealed;
+mod store;
+mod unlock;
+mod url_policy;
+mod webhook;
+
+pub use admin::{
+    LinkCursor, LinkQuery, LinkSort, LinkSummary, ListLinksReq, ListLinksRes, SortOrder,
+};
+pub use auth::{Admin, ApiKey, CreateKeyReq, CreateKeyRes, MaybeOwner, Owner};
+pub use bulk::{BulkItem, BulkRes};
+pub use cache::{CacheConfig, CacheStats};
+pub use client::{Client, ClientError};
+pub use config::{Config, ConfigArgs};
+pub use error::{AppError, ProblemDetails};
+pub use id::{ContentHash, IdGenerator, IdScheme, NanoId, ObfuscatedSequence, Sequence};
+pub use openapi::ApiDoc;
+pub use query::{QueryPassthrough, UtmParams};
+pub use routing::{Device, RedirectRule, Route, Routing, RuleCondition, SplitVariant};
+pub use sealed::{SealedLink, SealingKeys, SEALED_PREFIX};
+pub use store::{
+    open_store, MemoryStore, MigrationState, MigrationStatus, PgStore, SqliteStore, StoreError,
+    UrlStore,
+};
+pub use unlock::UnlockReq;
+pub use url_policy::{FragmentPolicy, UrlError, UrlPolicy};
+pub use webhook::{
+    sign_delivery, verify_delivery, ClickEvent, CreateWebhookReq, CreateWebhookRes, DeadLetter,
+    Webhook, WebhookRes, WebhookRetry, WEBHOOK_EVENT_HEADER, WEBHOOK_SIGNATURE_HEADER,
+};
+
+use std::{fmt, net::SocketAddr, str::FromStr, sync::Arc};
+
+use anyhow::Result;
+use axum::{
+    extract::{ConnectInfo, FromRequest, FromRequestParts, Path, RawQuery, State},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use http::{
+    header::{CACHE_CONTROL, LOCATION, REFERER, SET_COOKIE, USER_AGENT},
+    HeaderMap, HeaderValue, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tokio::sync::mpsc;
+use tokio_util::task::TaskTracker;
+use tracing::warn;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    api_docs,
+    health::{track_metrics, Health},
+    rate_limit::{rate_limit, Quota, RateLimiter},
+    shutdown::Shutdown,
+};
+use auth::ApiKeyCache;
+use cache::{Cached, RedirectCache};
+use webhook::{DeliveryPolicy, WebhookQueue};
+
+const CLICK_QUEUE_SIZE: usize = 1024;
+const CLICK_BATCH_SIZE: usize = 64;
+// how many ids to try before giving up on a new link
+const MAX_ID_ATTEMPTS: u32 = 5;
+const ALIAS_MIN_LEN: usize = 3;
+const ALIAS_MAX_LEN: usize = 32;
+// ids that clash with (current or planned) routes and can't be used as aliases
+const RESERVED_ALIASES: &[&str] = &[
+    "admin", "api", "bulk", "docs", "health", "healthz", "keys", "login", "logout", "metrics",
+    "openapi", "readyz", "stats", "static", "webhooks",
+];
+
+/// `axum::Json`, rejecting malformed bodies with problem details.
+#[derive(Debug, FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+struct AppJson<T>(T);
+
+/// `axum::extract::Query`, rejecting malformed query strings with problem details.
+#[derive(Debug, FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(AppError))]
+struct AppQuery<T>(T);
+
+/// `axum::Form`, rejecting malformed forms with problem details.
+#[derive(Debug, FromRequest)]
+#[from_request(via(axum::Form), rejection(AppError))]
+struct AppForm<T>(T);
+
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ShortenReq {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_clicks: Option<i32>,
+    /// the server's default when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<RedirectStatus>,
+    /// show visitors a preview page instead of redirecting them
+    #[serde(default)]
+    pub preview: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub utm: Option<UtmParams>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing: Option<Routing>,
+    /// visitors have to enter it before they are redirected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// seal the url (and expiry) into the id instead of storing the link
+    #[serde(default)]
+    pub sealed: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ShortenRes {
+    pub url: String,
+}
+
+/// Where a short url points, found without following it.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Resolution {
+    pub id: String,
+    /// what following the link answers with
+    pub status: u16,
+    /// missing when the link shows a page instead: a preview or a password form
+    pub location: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UpdateReq {
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct StatsRes {
+    pub id: String,
+    pub total: i64,
+    pub days: Vec<DayStats>,
+    /// clicks per rule or split variant, for links with routing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<VariantStats>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct DayStats {
+    pub day: NaiveDate,
+    pub clicks: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct VariantStats {
+    pub variant: String,
+    pub clicks: i64,
+}
+
+/// A stored link, as the store exports it for backups.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct LinkRecord {
+    pub id: String,
+    pub url: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_clicks: Option<i32>,
+    pub clicks: i32,
+    pub owner: Option<String>,
+    pub redirect: Option<RedirectStatus>,
+    pub preview: bool,
+    /// default utm parameters, as an encoded query string
+    pub utm: Option<String>,
+    /// [`Routing`] as json
+    pub routing: Option<String>,
+    pub password_hash: Option<String>,
+}
+
+/// Everything stored along with a new link's id.
+#[derive(Debug, Clone)]
+pub struct NewLink {
+    pub url: String,
+    pub limits: LinkLimits,
+    /// id of the api key that created the link, only the owner can change it
+    pub owner: Option<String>,
+    pub redirect: Option<RedirectStatus>,
+    pub preview: bool,
+    /// default utm parameters, as an encoded query string
+    pub utm: Option<String>,
+    /// [`Routing`] as json
+    pub routing: Option<String>,
+    /// argon2 hash of the password guarding the link
+    pub password: Option<String>,
+    /// whether the id is an alias picked by the creator
+    pub aliased: bool,
+}
+
+/// Optional limits after which a link stops redirecting.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LinkLimits {
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_clicks: Option<i32>,
+}
+
+/// How a link redirects, serialized as the status code. Browsers cache 301 and
+/// 308 for good, temporary campaign links should use 302 or 307.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema,
+)]
+#[serde(try_from = "u16", into = "u16")]
+#[repr(i16)]
+pub enum RedirectStatus {
+    MovedPermanently = 301,
+    Found = 302,
+    TemporaryRedirect = 307,
+    #[default]
+    PermanentRedirect = 308,
+}
+
+#[derive(Debug)]
+pub enum Resolved {
+    Url {
+        url: String,
+        limits: LinkLimits,
+        redirect: Option<RedirectStatus>,
+        preview: bool,
+        utm: Option<String>,
+        routing: Option<String>,
+        /// whether a password guards the link
+        protected: bool,
+    },
+    /// the link exists but has expired or used up its clicks
+    Gone,
+}
+
+/// A followed link, queued by `redirect` and written by the click writer task.
+#[derive(Debug, Clone)]
+pub struct Click {
+    pub url_id: String,
+    pub clicked_at: DateTime<Utc>,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: String,
+    /// the rule or split variant the click was sent to
+    pub variant: Option<String>,
+}
+
+/// Per-client quotas (by api key, or by ip for clients without a known one). Link
+/// creating routes and password submissions get their own, tighter quota on
+/// top of `default`.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimits {
+    pub default: Quota,
+    pub shorten: Quota,
+    pub bulk: Quota,
+    pub keys: Quota,
+    pub unlock: Quota,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            default: Quota::per_second(50),
+            shorten: Quota::per_minute(60),
+            bulk: Quota::per_minute(10),
+            keys: Quota::per_minute(10),
+            unlock: Quota::per_minute(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AppState {
+    store: Arc<dyn UrlStore>,
+    api_keys: ApiKeyCache,
+    ids: Arc<dyn IdGenerator>,
+    urls: Arc<UrlPolicy>,
+    cache: RedirectCache,
+    rate_limits: RateLimits,
+    base_url: Arc<str>,
+    redirect: RedirectStatus,
+    query: QueryPassthrough,
+    // hash of the token guarding the admin api, which is disabled without one
+    admin_token: Option<blake3::Hash>,
+    // signs the cookies of unlocked password protected links
+    cookie_key: [u8; 32],
+    // seal links into their ids, which are disabled without them
+    sealing_keys: Option<Arc<SealingKeys>>,
+    clicks: mpsc::Sender<Click>,
+    webhooks: WebhookQueue,
+    delivery: DeliveryPolicy,
+    // writing clicks and delivering webhooks, to wait for on shutdown
+    tasks: TaskTracker,
+}
+
+pub fn router(state: AppState) -> Router {
+    let limits = state.rate_limits;
+    let limit = |quota| {
+        let limiter = RateLimiter::new(quota).with_api_keys(state.api_keys.clone());
+        middleware::from_fn_with_state(limiter, rate_limit)
+    };
+    let health = Health::new().with_probe("store", state.store.clone());
+    Router::new()
+        .route("/", post(shorten).layer(limit(limits.shorten)))
+        .route("/bulk", post(bulk::bulk_shorten).layer(limit(limits.bulk)))
+        .route("/keys", post(create_key).layer(limit(limits.keys)))
+        .route(
+            "/:id",
+            post(unlock::unlock)
+                .layer(limit(limits.unlock))
+                .get(redirect)
+                .patch(update)
+                .delete(delete),
+        )
+        .route("/:id/stats", get(stats))
+        .route("/:id/info", get(resolve))
+        .route(
+            "/webhooks",
+            get(webhook::list_webhooks).post(webhook::create_webhook),
+        )
+        .route(
+            "/webhooks/:id",
+            axum::routing::delete(webhook::delete_webhook),
+        )
+        .route("/admin/cache", get(cache_stats))
+        .route("/admin/export", get(bulk::export))
+        .route("/admin/links", get(admin::list_links))
+        .route("/admin/dead-letters", get(webhook::dead_letters))
+        .layer(limit(limits.default))
+        // probes, scrapes and the api docs aren't rate limited
+        .merge(health.routes())
+        .merge(api_docs::routes(ApiDoc::openapi()))
+        .layer(middleware::from_fn_with_state(
+            health.metrics(),
+            track_metrics,
+        ))
+        .with_state(state)
+}
+
+/// Follows a link
+///
+/// Ids starting with `~` are sealed links, carrying their target themselves.
+/// A trailing `+` shows where the link goes without following (or counting) it.
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    tag = "links",
+    params(("id" = String, Path, description = "id or alias of the link")),
+    responses(
+        (status = "3XX", description = "Redirects to the target, with the link's status or the server's default (307 for links that expire or run out of clicks)",
+            headers(("location" = String, description = "the target, the visitor's query string merged in"))),
+        (status = 200, description = "The preview of the link, or the password form of a protected one",
+            body = String, content_type = "text/html"),
+        (status = 404, description = "No such link", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 410, description = "The link expired or used up its clicks", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn redirect(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RawQuery(query): RawQuery,
+    req_headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if let Some(token) = id.strip_prefix(SEALED_PREFIX) {
+        return state.redirect_sealed(token, query.as_deref());
+    }
+    // `/:id+` shows where a link goes without following (or counting) it
+    if let Some(id) = id.strip_suffix('+') {
+        let unlocked = unlock::is_unlocked(&state, id, &req_headers);
+        return state.preview(id, query.as_deref(), unlocked).await;
+    }
+    let unlocked = unlock::is_unlocked(&state, &id, &req_headers);
+    let resolved = state
+        .get_url(&id, unlocked)
+        .await
+        .map_err(AppError::store(format!("failed to resolve {id}")))?;
+    let Resolved::Url {
+        url,
+        limits,
+        redirect,
+        preview,
+        utm,
+        routing,
+        protected,
+    } = resolved
+    else {
+        return Err(AppError::Gone(format!("link {id} is no longer available")));
+    };
+    if protected && !unlocked {
+        return Ok(unlock::form(&state, &id, false));
+    }
+    let route = routing
+        .as_deref()
+        .and_then(|routing| state.route(&id, routing, &req_headers));
+    let variant = route.as_ref().map(|route| route.variant.clone());
+    if preview {
+        state.record_click(Click::new(id.clone(), addr, &req_headers, variant));
+        return state.preview(&id, query.as_deref(), unlocked).await;
+    }
+    let target = route
+        .as_ref()
+        .map_or(url.as_str(), |route| route.url.as_str());
+    let url = state.location(target, utm.as_deref(), query.as_deref());
+    // urls stored before they were validated may not make a valid header
+    let location = url
+        .parse()
+        .map_err(|e| AppError::internal(format!("invalid target url for {id}"), e))?;
+    let mut headers = HeaderMap::new();
+    headers.insert(LOCATION, location);
+    if routing.is_some() || protected || !limits.is_permanent() {
+        // where the link goes depends on the visitor, or it stops going there
+        // at some point: nothing may cache the redirect
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("private, no-store"));
+    }
+    if let Some(sticky) = route.and_then(|route| route.sticky) {
+        let cookie = sticky
+            .parse()
+            .map_err(|e| AppError::internal(format!("invalid split cookie for {id}"), e))?;
+        headers.insert(SET_COOKIE, cookie);
+    }
+    state.record_click(Click::new(id, addr, &req_headers, variant));
+    let status = StatusCode::from(redirect.unwrap_or(state.redirect_for(&limits)));
+    Ok((status, headers).into_response())
+}
+
+/// Shortens a url
+#[utoipa::path(
+    post,
+    path = "/",
+    tag = "links",
+    request_body(content = ShortenReq, example = json!({"url": "https://example.com/docs"})),
+    responses(
+        (status = 201, description = "The short url", body = ShortenRes),
+        (status = 400, description = "Malformed json", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Unknown api key", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Sealed links are disabled", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "The alias is already taken", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid url, alias, limits or routing", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security((), ("api_key" = []))
+)]
+async fn shorten(
+    State(state): State<AppState>,
+    MaybeOwner(owner): MaybeOwner,
+    AppJson(data): AppJson<ShortenReq>, // body executor only one  put it final
+) -> Result<impl IntoResponse, AppError> {
+    let id = state.create_link(&data, owner).await?;
+    let body = Json(ShortenRes {
+        url: state.short_url(&id),
+    });
+    Ok((StatusCode::CREATED, body))
+}
+
+/// Points a link to a new url
+#[utoipa::path(
+    patch,
+    path = "/{id}",
+    tag = "links",
+    params(("id" = String, Path, description = "id or alias of the link")),
+    request_body(content = UpdateReq, example = json!({"url": "https://example.com/docs/v2"})),
+    responses(
+        (status = 204, description = "The link was updated"),
+        (status = 400, description = "Malformed json", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or unknown api key", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "The link belongs to another api key", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such link", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid url", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []))
+)]
+async fn update(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Owner(owner): Owner,
+    AppJson(data): AppJson<UpdateReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let url = state.urls.normalize(&data.url)?;
+    state
+        .store
+        .update_url(&id, &owner, &url)
+        .await
+        .map_err(AppError::store(format!("failed to update {id}")))?;
+    state.cache.invalidate(&id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Deletes a link
+#[utoipa::path(
+    delete,
+    path = "/{id}",
+    tag = "links",
+    params(("id" = String, Path, description = "id or alias of the link")),
+    responses(
+        (status = 204, description = "The link was deleted"),
+        (status = 401, description = "Missing or unknown api key", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "The link belongs to another api key", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such link", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []))
+)]
+async fn delete(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Owner(owner): Owner,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .store
+        .delete(&id, &owner)
+        .await
+        .map_err(AppError::store(format!("failed to delete {id}")))?;
+    state.cache.invalidate(&id);
+    state.webhooks.invalidate(&id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Issues an api key
+#[utoipa::path(
+    post,
+    path = "/keys",
+    tag = "keys",
+    request_body(content = CreateKeyReq, example = json!({"name": "marketing"})),
+    responses(
+        (status = 201, description = "The key, shown only this once", body = CreateKeyRes),
+        (status = 400, description = "Malformed json", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Missing name", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn create_key(
+    State(state): State<AppState>,
+    AppJson(data): AppJson<CreateKeyReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let (api_key, key) = ApiKey::generate(data.name);
+    state
+        .store
+        .create_api_key(&api_key)
+        .await
+        .map_err(AppError::store("failed to create api key"))?;
+    let body = Json(CreateKeyRes {
+        id: api_key.id,
+        key,
+    });
+    Ok((StatusCode::CREATED, body))
+}
+
+/// Tells where a link goes
+///
+/// Like following it, but without counting a visit. Links routing visitors
+/// by device or split resolve to their default target.
+#[utoipa::path(
+    get,
+    path = "/{id}/info",
+    tag = "links",
+    params(("id" = String, Path, description = "id or alias of the link")),
+    responses(
+        (status = 200, description = "The status and location following the link answers with", body = Resolution),
+        (status = 404, description = "No such link", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 410, description = "The link expired or used up its clicks", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn resolve(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    req_headers: HeaderMap,
+) -> Result<Json<Resolution>, AppError> {
+    let unlocked = unlock::is_unlocked(&state, &id, &req_headers);
+    Ok(Json(state.resolve(&id, unlocked).await?))
+}
+
+/// Clicks of a link per day
+#[utoipa::path(
+    get,
+    path = "/{id}/stats",
+    tag = "links",
+    params(("id" = String, Path, description = "id or alias of the link")),
+    responses(
+        (status = 200, description = "Clicks per day, and per variant for routed links", body = StatsRes),
+        (status = 404, description = "No such link", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn stats(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let stats = state
+        .stats(&id)
+        .await
+        .map_err(AppError::store(format!("failed to load stats for {id}")))?;
+    Ok(Json(stats))
+}
+
+/// Hits and misses of the redirect cache
+#[utoipa::path(
+    get,
+    path = "/admin/cache",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Redirect cache counters", body = CacheStats),
+        (status = 401, description = "Missing or wrong admin token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "The admin api is disabled", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("admin_token" = []))
+)]
+async fn cache_stats(State(state): State<AppState>, _: Admin) -> Json<CacheStats> {
+    Json(state.cache.stats())
+}
+
+impl AppState {
+    /// Connects to the store the url points at: `memory`, `sqlite:...` or `postgres://...`,
+    /// and brings its schema up to date.
+    pub async fn try_new(url: &str) -> Result<Self> {
+        Ok(Self::from_store(open_store(url, true).await?))
+    }
+
+    /// Connects to the configured store and applies the rest of the config.
+    pub async fn from_config(config: &Config) -> Result<Self> {
+        let urls = config
+            .blocked_domains
+            .iter()
+            .fold(UrlPolicy::default(), |policy, domain| policy.block(domain));
+        let urls = config
+            .trusted_domains
+            .iter()
+            .fold(urls, |policy, domain| policy.trust(domain));
+        let store = open_store(&config.database_url, config.migrate).await?;
+        let mut state = Self {
+            ids: config.id_generator()?,
+            ..Self::from_store(store)
+        }
+        .with_base_url(&config.base_url)
+        .with_redirect(config.redirect)
+        .with_query_passthrough(config.query_passthrough)
+        .with_url_policy(urls);
+        if let Some(token) = &config.admin_token {
+            state = state.with_admin_token(token);
+        }
+        if let Some(secret) = &config.cookie_secret {
+            state = state.with_cookie_secret(secret);
+        }
+        if let Some(keys) = &config.sealing_keys {
+            state = state.with_sealing_keys(keys.clone());
+        }
+        if config.private_webhooks {
+            state = state.with_private_webhooks(true);
+        }
+        Ok(state)
+    }
+
+    /// Must be called within a tokio runtime, it spawns the click writer.
+    pub fn new(store: impl UrlStore + 'static) -> Self {
+        Self::from_store(Arc::new(store))
+    }
+
+    fn from_store(store: Arc<dyn UrlStore>) -> Self {
+        let tasks = TaskTracker::new();
+        let (tx, rx) = mpsc::channel(CLICK_QUEUE_SIZE);
+        tasks.spawn(write_clicks(store.clone(), rx));
+        let delivery = DeliveryPolicy::default();
+        let webhooks = WebhookQueue::new(store.clone(), delivery.clone(), tasks.clone());
+        Self {
+            api_keys: ApiKeyCache::new(store.clone()),
+            store,
+            ids: Arc::new(NanoId::default()),
+            urls: Arc::new(UrlPolicy::default()),
+            cache: RedirectCache::new(CacheConfig::default()),
+            rate_limits: RateLimits::default(),
+            base_url: Config::default().base_url.into(),
+            redirect: RedirectStatus::default(),
+            query: QueryPassthrough::default(),
+            admin_token: None,
+            cookie_key: rand::random(),
+            sealing_keys: None,
+            clicks: tx,
+            webhooks,
+            delivery,
+            tasks,
+        }
+    }
+
+    /// The background work of the state: queued clicks being written and
+    /// webhooks being delivered. It winds down once the state and its clones,
+    /// the router's included, are dropped; wait for it on shutdown.
+    pub fn tasks(&self) -> TaskTracker {
+        self.tasks.clone()
+    }
+
+    pub fn with_cache(mut self, config: CacheConfig) -> Self {
+        self.cache = RedirectCache::new(config);
+        self
+    }
+
+    pub fn with_rate_limits(mut self, limits: RateLimits) -> Self {
+        self.rate_limits = limits;
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').into();
+        self
+    }
+
+    /// Status of links created without one.
+    pub fn with_redirect(mut self, redirect: RedirectStatus) -> Self {
+        self.redirect = redirect;
+        self
+    }
+
+    /// What redirects do with the query string of the short url.
+    pub fn with_query_passthrough(mut self, query: QueryPassthrough) -> Self {
+        self.query = query;
+        self
+    }
+
+    /// Enables the admin api for requests bearing `token`.
+    pub fn with_admin_token(mut self, token: &str) -> Self {
+        self.admin_token = Some(blake3::hash(token.as_bytes()));
+        self
+    }
+
+    /// Signs unlock cookies with a key derived from `secret` instead of a
+    /// random one, so they stay valid across restarts and instances.
+    pub fn with_cookie_secret(mut self, secret: &str) -> Self {
+        self.cookie_key =
+            blake3::derive_key("ecosystem shortener unlock cookie", secret.as_bytes());
+        self
+    }
+
+    /// Enables sealed links, whose ids carry the url itself so redirecting
+    /// them needs no store.
+    pub fn with_sealing_keys(mut self, keys: SealingKeys) -> Self {
+        self.sealing_keys = Some(Arc::new(keys));
+        self
+    }
+
+    /// Replaces the retry policy of webhook deliveries.
+    pub fn with_webhook_retry(mut self, retry: WebhookRetry) -> Self {
+        self.delivery.retry = retry;
+        self.restart_webhooks()
+    }
+
+    /// Lets webhooks target loopback, link-local and private addresses, which
+    /// they are kept from by default so nobody can make the server call into
+    /// its own network.
+    pub fn with_private_webhooks(mut self, allow: bool) -> Self {
+        self.delivery.private_targets = allow;
+        self.restart_webhooks()
+    }
+
+    /// Once `shutdown` starts, webhook deliveries waiting for a retry are
+    /// stored as dead letters instead of being lost with the process.
+    pub fn with_shutdown(mut self, shutdown: &Shutdown) -> Self {
+        self.delivery.shutdown = shutdown.token().clone();
+        self.restart_webhooks()
+    }
+
+    // the delivery task takes its policy along, replacing the queue ends the old one
+    fn restart_webhooks(mut self) -> Self {
+        self.webhooks = WebhookQueue::new(
+            self.store.clone(),
+            self.delivery.clone(),
+            self.tasks.clone(),
+        );
+        self
+    }
+
+    pub fn with_url_policy(mut self, urls: UrlPolicy) -> Self {
+        self.urls = Arc::new(urls);
+        self
+    }
+
+    pub fn with_id_generator(mut self, ids: impl IdGenerator + 'static) -> Self {
+        self.ids = Arc::new(ids);
+        self
+    }
+
+    /// Validates the request and stores the link, returning its id.
+    async fn create_link(
+        &self,
+        data: &ShortenReq,
+        owner: Option<String>,
+    ) -> Result<String, AppError> {
+        if data.sealed {
+            return self.seal_link(data);
+        }
+        let limits = LinkLimits {
+            expires_at: data.expires_at,
+            max_clicks: data.max_clicks,
+        };
+        validate_limits(&limits).map_err(|e| AppError::Validation(e.to_string()))?;
+        let url = self.urls.normalize(&data.url)?;
+        let routing = match data.routing.clone().filter(|routing| !routing.is_empty()) {
+            Some(routing) => {
+                let routing = routing.validate(&self.urls).map_err(AppError::Validation)?;
+                let json = serde_json::to_string(&routing)
+                    .map_err(|e| AppError::internal("failed to encode routing", e))?;
+                Some(json)
+            }
+            None => None,
+        };
+        let password = match data.password.as_deref() {
+            Some(password) => Some(unlock::hash_password(password).await?),
+            None => None,
+        };
+        let link = NewLink {
+            url,
+            limits,
+            owner,
+            redirect: data.redirect,
+            preview: data.preview,
+            utm: data.utm.as_ref().and_then(UtmParams::to_query),
+            routing,
+            password,
+            aliased: data.alias.is_some(),
+        };
+        match data.alias.as_deref() {
+            Some(alias) => {
+                validate_alias(alias).map_err(|e| AppError::Validation(e.to_string()))?;
+                self.shorten_with_alias(alias, &link).await
+            }
+            None => self.shorten(&link).await,
+        }
+        .map_err(|e| match e {
+            StoreError::IdTaken(_) if data.alias.is_some() => {
+                AppError::Conflict("alias is already taken".to_string())
+            }
+            // every generated candidate was taken
+            StoreError::IdTaken(_) => AppError::internal("failed to generate a free id", e),
+            e => AppError::store("failed to shorten url")(e),
+        })
+    }
+
+    // a sealed link's id, nothing is stored so nothing but the url and expiry fits
+    fn seal_link(&self, data: &ShortenReq) -> Result<String, AppError> {
+        let keys = self
+            .sealing_keys
+            .as_ref()
+            .ok_or_else(|| AppError::Forbidden("sealed links are disabled".to_string()))?;
+        let stored_only = data.alias.is_some()
+            || data.max_clicks.is_some()
+            || data.redirect.is_some()
+            || data.preview
+            || data.utm.is_some()
+            || data.routing.is_some()
+            || data.password.is_some();
+        if stored_only {
+            return Err(AppError::Validation(
+                "sealed links only take a url and an expiry".to_string(),
+            ));
+        }
+        let limits = LinkLimits {
+            expires_at: data.expires_at,
+            max_clicks: None,
+        };
+        validate_limits(&limits).map_err(|e| AppError::Validation(e.to_string()))?;
+        let link = SealedLink {
+            url: self.urls.normalize(&data.url)?,
+            expires_at: data.expires_at,
+        };
+        let token = keys
+            .seal(&link)
+            .map_err(|e| AppError::internal("failed to seal link", anyhow::anyhow!(e)))?;
+        Ok(format!("{SEALED_PREFIX}{token}"))
+    }
+
+    // opens the link sealed into a `~` id, no store, cache or click involved
+    fn redirect_sealed(&self, token: &str, query: Option<&str>) -> Result<Response, AppError> {
+        let link = self.open_sealed(token)?;
+        let url = self.location(&link.url, None, query);
+        let location: HeaderValue = url
+            .parse()
+            .map_err(|e| AppError::internal("invalid target url of a sealed link", e))?;
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCATION, location);
+        let limits = LinkLimits {
+            expires_at: link.expires_at,
+            max_clicks: None,
+        };
+        if !limits.is_permanent() {
+            headers.insert(CACHE_CONTROL, HeaderValue::from_static("private, no-store"));
+        }
+        Ok((StatusCode::from(self.redirect_for(&limits)), headers).into_response())
+    }
+
+    // the link sealed into `token`, unless it expired
+    fn open_sealed(&self, token: &str) -> Result<SealedLink, AppError> {
+        let not_found = || AppError::NotFound(format!("link {SEALED_PREFIX}{token} not found"));
+        let link = self
+            .sealing_keys
+            .as_ref()
+            .and_then(|keys| keys.open(token))
+            .ok_or_else(not_found)?;
+        if link.is_expired() {
+            return Err(AppError::Gone(format!(
+                "link {SEALED_PREFIX}{token} is no longer available"
+            )));
+        }
+        Ok(link)
+    }
+
+    // what a visit of `id` would get, without counting one
+    async fn resolve(&self, id: &str, unlocked: bool) -> Result<Resolution, AppError> {
+        if let Some(token) = id.strip_prefix(SEALED_PREFIX) {
+            let link = self.open_sealed(token)?;
+            let limits = LinkLimits {
+                expires_at: link.expires_at,
+                max_clicks: None,
+            };
+            return Ok(Resolution {
+                id: id.to_string(),
+                status: StatusCode::from(self.redirect_for(&limits)).as_u16(),
+                location: Some(self.location(&link.url, None, None)),
+            });
+        }
+        let link = self
+            .store
+            .find_link(id)
+            .await
+            .map_err(AppError::store(format!("failed to find {id}")))?;
+        if link.is_gone() {
+            return Err(AppError::Gone(format!("link {id} is no longer available")));
+        }
+        if link.preview || (link.protected && !unlocked) {
+            return Ok(Resolution {
+                id: id.to_string(),
+                status: StatusCode::OK.as_u16(),
+                location: None,
+            });
+        }
+        let limits = LinkLimits {
+            expires_at: link.expires_at,
+            max_clicks: link.max_clicks,
+        };
+        let status = link.redirect.unwrap_or(self.redirect_for(&limits));
+        Ok(Resolution {
+            id: id.to_string(),
+            status: StatusCode::from(status).as_u16(),
+            location: Some(self.location(&link.url, link.utm.as_deref(), None)),
+        })
+    }
+
+    // the server's status for links without their own, temporary for links
+    // that stop redirecting: a cached permanent redirect would outlive them
+    fn redirect_for(&self, limits: &LinkLimits) -> RedirectStatus {
+        if limits.is_permanent() {
+            self.redirect
+        } else {
+            RedirectStatus::TemporaryRedirect
+        }
+    }
+
+    fn short_url(&self, id: &str) -> String {
+        format!("{}/{}", self.base_url, id)
+    }
+
+    async fn shorten(&self, link: &NewLink) -> Result<String, StoreError> {
+        let mut attempt = 0;
+        let id = loop {
+            let id = self.ids.generate(&link.url, attempt);
+            match self.store.shorten(&id, link).await {
+                Err(StoreError::IdTaken(id)) if attempt + 1 < MAX_ID_ATTEMPTS => {
+                    warn!("Generated id {} is already taken, retrying", id);
+                    attempt += 1;
+                }
+                ret => break ret?,
+            }
+        };
+        // the id may have been remembered as missing
+        self.cache.invalidate(&id);
+        Ok(id)
+    }
+
+    async fn shorten_with_alias(&self, alias: &str, link: &NewLink) -> Result<String, StoreError> {
+        // no upsert here: a taken alias must surface as a conflict
+        self.store.insert(alias, link).await?;
+        self.cache.invalidate(alias);
+        Ok(alias.to_string())
+    }
+
+    async fn get_url(&self, id: &str, unlocked: bool) -> Result<Resolved, StoreError> {
+        match self.cache.get(id) {
+            Some(Cached::Url {
+                url,
+                expires_at,
+                redirect,
+                preview,
+                utm,
+                routing,
+                protected,
+            }) => {
+                let limits = LinkLimits {
+                    expires_at,
+                    max_clicks: None,
+                };
+                return Ok(Resolved::Url {
+                    url,
+                    limits,
+                    redirect,
+                    preview,
+                    utm,
+                    routing,
+                    protected,
+                });
+            }
+            Some(Cached::Missing) => return Err(StoreError::NotFound(id.to_string())),
+            None => {}
+        }
+        let ret = self.store.get_url(id, unlocked).await;
+        match &ret {
+            // links with a click budget must hit the store so every click is counted
+            Ok(Resolved::Url {
+                url,
+                limits,
+                redirect,
+                preview,
+                utm,
+                routing,
+                protected,
+            }) if limits.max_clicks.is_none() => {
+                let cached = Cached::Url {
+                    url: url.clone(),
+                    expires_at: limits.expires_at,
+                    redirect: *redirect,
+                    preview: *preview,
+                    utm: utm.clone(),
+                    routing: routing.clone(),
+                    protected: *protected,
+                };
+                self.cache.insert(id, cached);
+            }
+            Err(StoreError::NotFound(_)) => self.cache.insert(id, Cached::Missing),
+            _ => {}
+        }
+        ret
+    }
+
+    // the target with the link's utm parameters and the visitor's query merged in
+    fn location(&self, url: &str, utm: Option<&str>, query: Option<&str>) -> String {
+        query::location(url, utm, query, self.query)
+    }
+
+    // where a visitor goes by the link's routing, `None` for the link's url
+    fn route(&self, id: &str, routing: &str, headers: &HeaderMap) -> Option<Route> {
+        match serde_json::from_str::<Routing>(routing) {
+            Ok(routing) => routing.route(id, headers),
+            Err(e) => {
+                warn!("Ignoring invalid routing of {}: {}", id, e);
+                None
+            }
+        }
+    }
+
+    async fn preview(
+        &self,
+        id: &str,
+        query: Option<&str>,
+        unlocked: bool,
+    ) -> Result<Response, AppError> {
+        let link = self
+            .store
+            .find_link(id)
+            .await
+            .map_err(AppError::store(format!("failed to find {id}")))?;
+        if link.is_gone() {
+            return Err(AppError::Gone(format!("link {id} is no longer available")));
+        }
+        // the preview would give the destination away
+        if link.protected && !unlocked {
+            return Ok(unlock::form(self, id, false));
+        }
+        let location = self.location(&link.url, link.utm.as_deref(), query);
+        Ok(preview::page(self, &link, &location).into_response())
+    }
+
+    /// Queues a click without waiting for it to be written or delivered to webhooks.
+    fn record_click(&self, click: Click) {
+        self.webhooks.enqueue(&click);
+        if let Err(e) = self.clicks.try_send(click) {
+            warn!("Dropping click: {}", e);
+        }
+    }
+
+    async fn stats(&self, id: &str) -> Result<StatsRes, StoreError> {
+        self.store.stats(id).await
+    }
+}
+
+impl LinkLimits {
+    /// Permanent links never stop redirecting.
+    pub fn is_permanent(&self) -> bool {
+        self.expires_at.is_none() && self.max_clicks.is_none()
+    }
+}
+
+impl NewLink {
+    /// Plain links are permanent, not aliased and have no settings of their
+    /// own, only they are deduplicated by url (per owner).
+    pub fn is_plain(&self) -> bool {
+        self.limits.is_permanent()
+            && self.redirect.is_none()
+            && !self.preview
+            && self.utm.is_none()
+            && self.routing.is_none()
+            && self.password.is_none()
+            && !self.aliased
+    }
+}
+
+impl From<RedirectStatus> for u16 {
+    fn from(redirect: RedirectStatus) -> Self {
+        redirect as u16
+    }
+}
+
+impl From<RedirectStatus> for StatusCode {
+    fn from(redirect: RedirectStatus) -> Self {
+        match redirect {
+            RedirectStatus::MovedPermanently => StatusCode::MOVED_PERMANENTLY,
+            RedirectStatus::Found => StatusCode::FOUND,
+            RedirectStatus::TemporaryRedirect => StatusCode::TEMPORARY_REDIRECT,
+            RedirectStatus::PermanentRedirect => StatusCode::PERMANENT_REDIRECT,
+        }
+    }
+}
+
+impl TryFrom<u16> for RedirectStatus {
+    type Error = String;
+
+    fn try_from(status: u16) -> Result<Self, Self::Error> {
+        match status {
+            301 => Ok(Self::MovedPermanently),
+            302 => Ok(Self::Found),
+            307 => Ok(Self::TemporaryRedirect),
+            308 => Ok(Self::PermanentRedirect),
+            _ => Err(format!(
+                "redirect must be one of 301, 302, 307 or 308, not {status}"
+            )),
+        }
+    }
+}
+
+impl FromStr for RedirectStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let status: u16 = s
+            .trim()
+            .parse()
+            .map_err(|_| format!("redirect must be a status code, not {s:?}"))?;
+        status.try_into()
+    }
+}
+
+impl fmt::Display for RedirectStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", u16::from(*self))
+    }
+}
+
+impl StatsRes {
+    fn new(id: impl Into<String>, days: Vec<DayStats>, variants: Vec<VariantStats>) -> Self {
+        Self {
+            id: id.into(),
+            total: days.iter().map(|d| d.clicks).sum(),
+            days,
+            variants,
+        }
+    }
+}
+
+impl Click {
+    fn new(url_id: String, addr: SocketAddr, headers: &HeaderMap, variant: Option<String>) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            url_id,
+            clicked_at: Utc::now(),
+            referrer: header(REFERER.as_str()),
+            user_agent: header(USER_AGENT.as_str()),
+            // the peer, like the rate limits: visitors can forge forwarded headers
+            ip: addr.ip().to_string(),
+            variant,
+        }
+    }
+}
+
+/// Drains the click queue, writing clicks in batches.
+async fn write_clicks(store: Arc<dyn UrlStore>, mut rx: mpsc::Receiver<Click>) {
+    let mut batch = Vec::with_capacity(CLICK_BATCH_SIZE);
+    while rx.recv_many(&mut batch, CLICK_BATCH_SIZE).await > 0 {
+        if let Err(e) = store.record_clicks(&batch).await {
+            warn!("Failed to write clicks: {:?}", e);
+        }
+        batch.clear();
+    }
+}
+
+fn validate_alias(alias: &str) -> Result<(), &'static str> {
+    if alias.len() < ALIAS_MIN_LEN || alias.len() > ALIAS_MAX_LEN {
+        return Err("alias must be between 3 and 32 characters");
+    }
+    if !alias
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("alias may only contain ASCII letters, digits, '-' and '_'");
+    }
+    if alias.starts_with(['-', '_']) || alias.ends_with(['-', '_']) {
+        return Err("alias must start and end with a letter or digit");
+    }
+    if RESERVED_ALIASES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(alias))
+    {
+        return Err("alias is reserved");
+    }
+    Ok(())
+}
+
+fn validate_limits(limits: &LinkLimits) -> Result<(), &'static str> {
+    if matches!(limits.expires_at, Some(expires_at) if expires_at <= Utc::now()) {
+        return Err("expires_at must be in the future");
+    }
+    if matches!(limits.max_clicks, Some(max_clicks) if max_clicks < 1) {
+        return Err("max_clicks must be at least 1");
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use utoipa::{
+    openapi::{
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+        OpenApi as Spec,
+    },
+    Modify, OpenApi,
+};
+
+use crate::health::HealthApi;
+
+/// The OpenAPI document of the shortener, generated from its handlers and the
+/// types they speak. Served at `/openapi.json`.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "shortener",
+        description = "Shortens urls and redirects to them.\n\n\
+            Every route but the probes is rate limited per client, answering `429` \
+            with a `Retry-After` header once the quota is used up. Routes using the \
+            store answer `503` with problem details while it can't be reached."
+    ),
+    paths(
+        super::shorten,
+        super::bulk::bulk_shorten,
+        super::create_key,
+        super::redirect,
+        super::unlock::unlock,
+        super::update,
+        super::delete,
+        super::resolve,
+        super::stats,
+        super::webhook::list_webhooks,
+        super::webhook::create_webhook,
+        super::webhook::delete_webhook,
+        super::cache_stats,
+        super::bulk::export,
+        super::admin::list_links,
+        super::webhook::dead_letters,
+    ),
+    modifiers(&Service),
+    tags(
+        (name = "links", description = "Short links, their redirects and stats"),
+        (name = "keys", description = "Api keys owning links"),
+        (name = "webhooks", description = "Endpoints notified of clicks"),
+        (name = "admin", description = "Operating the shortener"),
+    )
+)]
+pub struct ApiDoc;
+
+// what the handlers don't say themselves: the bearer tokens they take, and
+// the probes merged into the router along with them
+struct Service;
+
+impl Modify for Service {
+    fn modify(&self, spec: &mut Spec) {
+        // the crate has no license to name, utoipa would still emit an empty one
+        spec.info.license = None;
+        let components = spec.components.get_or_insert_with(Default::default);
+        let bearer = |description: &str| {
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some(description))
+                    .build(),
+            )
+        };
+        components.add_security_scheme(
+            "api_key",
+            bearer("An api key from `POST /keys`, owning the links created with it"),
+        );
+        components.add_security_scheme(
+            "admin_token",
+            bearer("The configured admin token, the admin api is disabled without one"),
+        );
+        spec.merge(HealthApi::openapi());
+    }
+}
@@ -0,0 +1,73 @@
+use axum::response::Html;
+
+use super::{url_policy, AppState, LinkSummary};
+
+/// The interstitial shown instead of redirecting: where the link goes
+/// (`location`, its target with query parameters merged in), when it was
+/// created, and a warning if the destination isn't on a trusted domain.
+pub(super) fn page(state: &AppState, link: &LinkSummary, location: &str) -> Html<String> {
+    let short_url = escape(&state.short_url(&link.id));
+    let url = escape(location);
+    let host = url_policy::host(&link.url).unwrap_or_default();
+    let warning = if state.urls.is_trusted(&link.url) {
+        String::new()
+    } else {
+        format!(
+            r#"<p class="warning"><strong>{}</strong> is not a domain we know. Only continue if you trust it.</p>"#,
+            escape(&host)
+        )
+    };
+    let expires = link
+        .expires_at
+        .map(|expires_at| format!("<dt>Expires</dt><dd>{}</dd>", expires_at.to_rfc2822()))
+        .unwrap_or_default();
+    // urls stored before they were validated may not be http(s), never link to those
+    let continue_link = if link.url.starts_with("https://") || link.url.starts_with("http://") {
+        format!(
+            r#"<p><a href="{url}" rel="noopener noreferrer nofollow">Continue to {host}</a></p>"#,
+            host = escape(&host)
+        )
+    } else {
+        String::new()
+    };
+    Html(format!(
+        r#"<!doctype html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>Preview of {short_url}</title>
+<style>
+body {{ font-family: system-ui, sans-serif; max-width: 40rem; margin: 4rem auto; padding: 0 1rem; line-height: 1.5; }}
+code {{ word-break: break-all; }}
+.warning {{ background: #fff3cd; border: 1px solid #ffe69c; padding: 0.75rem 1rem; }}
+</style>
+</head>
+<body>
+<h1>{short_url} leads to</h1>
+<p><code>{url}</code></p>
+{warning}
+<dl><dt>Created</dt><dd>{created}</dd>{expires}</dl>
+{continue_link}
+</body>
+</html>
+"#,
+        created = link.created_at.to_rfc2822(),
+    ))
+}
+
+pub(super) fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
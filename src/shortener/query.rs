@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// What `redirect` does with the query string of the short url.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryPassthrough {
+    /// drop it, the target is used as stored
+    Off,
+    /// add its parameters to the target, unless the target already sets them
+    #[default]
+    Merge,
+    /// add its parameters to the target, replacing the ones the target sets
+    Override,
+}
+
+/// Default `utm_*` parameters of a link. They are added on redirect unless the
+/// target or the visitor's query string already sets them, so one link can be
+/// shared on several channels and still be attributed to each.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct UtmParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub medium: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub campaign: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub term: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+impl UtmParams {
+    /// The parameters as they are stored: an encoded query string, or `None`
+    /// if none are set.
+    pub fn to_query(&self) -> Option<String> {
+        let params = [
+            ("utm_source", &self.source),
+            ("utm_medium", &self.medium),
+            ("utm_campaign", &self.campaign),
+            ("utm_term", &self.term),
+            ("utm_content", &self.content),
+        ];
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        let mut empty = true;
+        for (name, value) in params {
+            if let Some(value) = value.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+                query.append_pair(name, value);
+                empty = false;
+            }
+        }
+        (!empty).then(|| query.finish())
+    }
+
+    /// The parameters of a query string as `to_query` writes it, others are
+    /// ignored.
+    pub fn from_query(query: &str) -> Self {
+        let mut utm = Self::default();
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            let param = match name.as_ref() {
+                "utm_source" => &mut utm.source,
+                "utm_medium" => &mut utm.medium,
+                "utm_campaign" => &mut utm.campaign,
+                "utm_term" => &mut utm.term,
+                "utm_content" => &mut utm.content,
+                _ => continue,
+            };
+            *param = Some(value.into_owned());
+        }
+        utm
+    }
+}
+
+/// Where to redirect to: the target with the link's `utm` parameters and the
+/// visitor's query merged in. Parameters are appended as they come, the target
+/// is never re-encoded.
+pub(crate) fn location(
+    target: &str,
+    utm: Option<&str>,
+    incoming: Option<&str>,
+    mode: QueryPassthrough,
+) -> String {
+    let (target, fragment) = match target.split_once('#') {
+        Some((target, fragment)) => (target, Some(fragment)),
+        None => (target, None),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let incoming = match mode {
+        QueryPassthrough::Off => vec![],
+        _ => params(incoming.unwrap_or_default()),
+    };
+    let mut merged = params(query);
+    if mode == QueryPassthrough::Override {
+        merged.retain(|param| !has(&incoming, name(param)));
+    }
+    // the visitor's parameters win over the link's defaults
+    for param in params(utm.unwrap_or_default()) {
+        if !has(&merged, name(param)) && !has(&incoming, name(param)) {
+            merged.push(param);
+        }
+    }
+    let taken = merged.clone();
+    merged.extend(incoming.into_iter().filter(|p| !has(&taken, name(p))));
+
+    let mut location = path.to_string();
+    if !merged.is_empty() {
+        location.push('?');
+        location.push_str(&merged.join("&"));
+    } else if target.contains('?') {
+        // keep a bare `?` the target was stored with
+        location.push('?');
+    }
+    if let Some(fragment) = fragment {
+        location.push('#');
+        location.push_str(fragment);
+    }
+    location
+}
+
+fn params(query: &str) -> Vec<&str> {
+    query.split('&').filter(|p| !p.is_empty()).collect()
+}
+
+fn name(param: &str) -> &str {
+    param.split_once('=').map_or(param, |(name, _)| name)
+}
+
+fn has(params: &[&str], param_name: &str) -> bool {
+    params.iter().any(|p| name(p) == param_name)
+}
@@ -0,0 +1,234 @@
+use std::collections::HashSet;
+
+use http::{
+    header::{ACCEPT_LANGUAGE, COOKIE, USER_AGENT},
+    HeaderMap,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{UrlError, UrlPolicy};
+
+const MAX_RULES: usize = 20;
+const VARIANT_MAX_LEN: usize = 32;
+// how long a visitor sticks to the split variant they were assigned
+const STICKY_MAX_AGE: u64 = 30 * 24 * 60 * 60;
+
+/// Per-link destinations besides the link's url. Rules are tried in order and
+/// the first matching one wins; if none does, visitors are split between the
+/// `split` variants by weight, or sent to the link's url without any.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Routing {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<RedirectRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub split: Vec<SplitVariant>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RedirectRule {
+    /// the variant clicks sent by this rule are counted under
+    pub name: String,
+    pub url: String,
+    #[serde(flatten)]
+    pub when: RuleCondition,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleCondition {
+    /// the class of device the `User-Agent` belongs to
+    Device(Device),
+    /// the visitor's preferred `Accept-Language`, `de` also matches `de-AT`
+    Language(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Device {
+    Ios,
+    Android,
+    /// anything else
+    Desktop,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SplitVariant {
+    pub name: String,
+    pub url: String,
+    /// share of the visitors relative to the other variants
+    pub weight: u32,
+}
+
+/// Where a routed visitor goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub variant: String,
+    pub url: String,
+    /// `Set-Cookie` value pinning a newly split visitor to their variant
+    pub sticky: Option<String>,
+}
+
+impl Routing {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.split.is_empty()
+    }
+
+    /// Checks the rules and normalizes their urls like the link's own.
+    pub(crate) fn validate(mut self, urls: &UrlPolicy) -> Result<Self, String> {
+        if self.rules.len() + self.split.len() > MAX_RULES {
+            return Err(format!(
+                "a link can have at most {MAX_RULES} rules and variants"
+            ));
+        }
+        let mut names = HashSet::new();
+        let variants = self
+            .rules
+            .iter_mut()
+            .map(|r| (&mut r.name, &mut r.url))
+            .chain(self.split.iter_mut().map(|v| (&mut v.name, &mut v.url)));
+        for (name, url) in variants {
+            validate_variant(name)?;
+            if !names.insert(name.clone()) {
+                return Err(format!("variant {name} is used more than once"));
+            }
+            *url = urls.normalize(url).map_err(|e: UrlError| e.to_string())?;
+        }
+        for rule in &mut self.rules {
+            if let RuleCondition::Language(language) = &mut rule.when {
+                *language = language.trim().to_ascii_lowercase();
+                if language.is_empty() {
+                    return Err("language must not be empty".to_string());
+                }
+            }
+        }
+        if self.split.iter().any(|v| v.weight == 0) {
+            return Err("split weights must be at least 1".to_string());
+        }
+        Ok(self)
+    }
+
+    /// Picks the destination for a visitor of link `id`, `None` for the link's url.
+    pub fn route(&self, id: &str, headers: &HeaderMap) -> Option<Route> {
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        let rule = self.rules.iter().find(|rule| match &rule.when {
+            RuleCondition::Device(device) => *device == Device::of(header(USER_AGENT)),
+            RuleCondition::Language(language) => preferred_language(header(ACCEPT_LANGUAGE))
+                .is_some_and(|preferred| {
+                    preferred == *language || preferred.starts_with(&format!("{language}-"))
+                }),
+        });
+        if let Some(rule) = rule {
+            return Some(Route {
+                variant: rule.name.clone(),
+                url: rule.url.clone(),
+                sticky: None,
+            });
+        }
+
+        let cookie = sticky_cookie(id);
+        let assigned = header(COOKIE)
+            .and_then(|cookies| cookie_value(cookies, &cookie))
+            .and_then(|name| self.split.iter().find(|v| v.name == name));
+        if let Some(variant) = assigned {
+            return Some(Route {
+                variant: variant.name.clone(),
+                url: variant.url.clone(),
+                sticky: None,
+            });
+        }
+        let variant = self.pick()?;
+        Some(Route {
+            variant: variant.name.clone(),
+            url: variant.url.clone(),
+            sticky: Some(format!(
+                "{cookie}={}; Path=/{id}; Max-Age={STICKY_MAX_AGE}; HttpOnly; SameSite=Lax",
+                variant.name
+            )),
+        })
+    }
+
+    // a weighted random split variant
+    fn pick(&self) -> Option<&SplitVariant> {
+        let total: u64 = self.split.iter().map(|v| u64::from(v.weight)).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut n = rand::thread_rng().gen_range(0..total);
+        self.split.iter().find(|v| {
+            let weight = u64::from(v.weight);
+            if n < weight {
+                return true;
+            }
+            n -= weight;
+            false
+        })
+    }
+}
+
+impl Device {
+    fn of(user_agent: Option<&str>) -> Self {
+        let user_agent = user_agent.unwrap_or_default();
+        if ["iPhone", "iPad", "iPod"]
+            .iter()
+            .any(|device| user_agent.contains(device))
+        {
+            Self::Ios
+        } else if user_agent.contains("Android") {
+            Self::Android
+        } else {
+            Self::Desktop
+        }
+    }
+}
+
+// variant names end up in cookies and stats, keep them to a safe alphabet
+fn validate_variant(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > VARIANT_MAX_LEN {
+        return Err(format!(
+            "variant names must be between 1 and {VARIANT_MAX_LEN} characters"
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("variant names may only contain ASCII letters, digits, '-' and '_'".into());
+    }
+    Ok(())
+}
+
+fn sticky_cookie(id: &str) -> String {
+    format!("split_{id}")
+}
+
+fn cookie_value<'a>(cookies: &'a str, name: &str) -> Option<&'a str> {
+    cookies
+        .split(';')
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+// the highest weighted language tag, lowercased
+fn preferred_language(accept_language: Option<&str>) -> Option<String> {
+    accept_language?
+        .split(',')
+        .filter_map(|tag| {
+            let mut parts = tag.split(';');
+            let language = parts.next()?.trim();
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            (!language.is_empty() && language != "*" && q > 0.0).then_some((language, q))
+        })
+        // the first of equally weighted tags wins
+        .fold(
+            None,
+            |best: Option<(&str, f32)>, (language, q)| match best {
+                Some((_, best_q)) if best_q >= q => best,
+                _ => Some((language, q)),
+            },
+        )
+        .map(|(language, _)| language.to_ascii_lowercase())
+}
@@ -0,0 +1,196 @@
+use std::{fmt, str::FromStr};
+
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use chacha20poly1305::{
+    aead::{self, Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305,
+};
+use chrono::{DateTime, Utc};
+use miniz_oxide::{deflate::compress_to_vec, inflate::decompress_to_vec_with_limit};
+use serde::Deserialize;
+
+/// Marks a sealed id, a character neither aliases nor generated ids use.
+pub const SEALED_PREFIX: char = '~';
+
+const VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+// version and key id, authenticated along with the ciphertext
+const HEADER_LEN: usize = 2;
+const HAS_EXPIRY: u8 = 1;
+const DEFLATED: u8 = 2;
+const COMPRESSION_LEVEL: u8 = 9;
+// far beyond any url the policy lets through, bounds what a token can inflate to
+const MAX_URL_LEN: usize = 16 * 1024;
+
+/// What a sealed id carries in place of a stored link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SealedLink {
+    pub url: String,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// The ChaCha20-Poly1305 keys sealing links into their ids, by key id. Links
+/// are sealed with the current key, the others only open links sealed before
+/// a rotation. Parsed from `id:base64key,...`, the first one being current.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SealingKeys {
+    current: (u8, [u8; 32]),
+    retired: Vec<(u8, [u8; 32])>,
+}
+
+impl SealedLink {
+    pub fn is_expired(&self) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= Utc::now())
+    }
+}
+
+impl SealingKeys {
+    pub fn new(id: u8, key: [u8; 32]) -> Self {
+        Self {
+            current: (id, key),
+            retired: vec![],
+        }
+    }
+
+    /// Adds a retired key, still opening the links it sealed. The id of a
+    /// known key is ignored.
+    pub fn with_retired(mut self, id: u8, key: [u8; 32]) -> Self {
+        if !self.ids().any(|known| known == id) {
+            self.retired.push((id, key));
+        }
+        self
+    }
+
+    fn ids(&self) -> impl Iterator<Item = u8> + '_ {
+        std::iter::once(self.current.0).chain(self.retired.iter().map(|(id, _)| *id))
+    }
+
+    /// The url safe token for `link`: the header, a random nonce and the
+    /// sealed, possibly deflated, link.
+    pub fn seal(&self, link: &SealedLink) -> Result<String, aead::Error> {
+        let mut flags = 0;
+        let mut plaintext = vec![];
+        if let Some(expires_at) = link.expires_at {
+            flags |= HAS_EXPIRY;
+            plaintext.extend_from_slice(&expires_at.timestamp().to_be_bytes());
+        }
+        let deflated = compress_to_vec(link.url.as_bytes(), COMPRESSION_LEVEL);
+        if deflated.len() < link.url.len() {
+            flags |= DEFLATED;
+            plaintext.extend_from_slice(&deflated);
+        } else {
+            plaintext.extend_from_slice(link.url.as_bytes());
+        }
+        plaintext.insert(0, flags);
+
+        let (id, key) = &self.current;
+        let header = [VERSION, *id];
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng); // 96-bits; unique per message
+        let payload = Payload {
+            msg: &plaintext,
+            aad: &header,
+        };
+        let ciphertext = ChaCha20Poly1305::new(key.into()).encrypt(&nonce, payload)?;
+        let token: Vec<_> = header.into_iter().chain(nonce).chain(ciphertext).collect();
+        Ok(URL_SAFE_NO_PAD.encode(token))
+    }
+
+    /// The link sealed into `token`, `None` if the token was tampered with,
+    /// is malformed or sealed with an unknown key. Expiry is left to the caller.
+    pub fn open(&self, token: &str) -> Option<SealedLink> {
+        let token = URL_SAFE_NO_PAD.decode(token).ok()?;
+        if token.len() < HEADER_LEN + NONCE_LEN || token[0] != VERSION {
+            return None;
+        }
+        let (header, rest) = token.split_at(HEADER_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: header,
+        };
+        let plaintext = self
+            .cipher(header[1])?
+            .decrypt(nonce.into(), payload)
+            .ok()?;
+
+        let (&flags, mut rest) = plaintext.split_first()?;
+        let mut expires_at = None;
+        if flags & HAS_EXPIRY != 0 {
+            let (timestamp, url) = rest.split_first_chunk::<8>()?;
+            expires_at = Some(DateTime::from_timestamp(i64::from_be_bytes(*timestamp), 0)?);
+            rest = url;
+        }
+        let url = if flags & DEFLATED != 0 {
+            decompress_to_vec_with_limit(rest, MAX_URL_LEN).ok()?
+        } else {
+            rest.to_vec()
+        };
+        Some(SealedLink {
+            url: String::from_utf8(url).ok()?,
+            expires_at,
+        })
+    }
+
+    fn cipher(&self, id: u8) -> Option<ChaCha20Poly1305> {
+        std::iter::once(&self.current)
+            .chain(&self.retired)
+            .find(|(known, _)| *known == id)
+            .map(|(_, key)| ChaCha20Poly1305::new(key.into()))
+    }
+}
+
+impl FromStr for SealingKeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keys: Option<Self> = None;
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (id, key) = entry
+                .split_once(':')
+                .ok_or_else(|| "sealing keys must look like `id:base64key`".to_string())?;
+            let id: u8 = id
+                .trim()
+                .parse()
+                .map_err(|_| format!("sealing key id must be 0 to 255, not {id:?}"))?;
+            let key: [u8; 32] = STANDARD
+                .decode(key.trim())
+                .ok()
+                .and_then(|key| key.try_into().ok())
+                .ok_or_else(|| format!("sealing key {id} must be 32 base64 encoded bytes"))?;
+            if keys
+                .as_ref()
+                .is_some_and(|keys| keys.ids().any(|known| known == id))
+            {
+                return Err(format!("sealing key id {id} is used more than once"));
+            }
+            keys = Some(match keys {
+                Some(keys) => keys.with_retired(id, key),
+                None => Self::new(id, key),
+            });
+        }
+        keys.ok_or_else(|| "at least one sealing key is needed".to_string())
+    }
+}
+
+impl TryFrom<String> for SealingKeys {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+// never print the keys themselves
+impl fmt::Debug for SealingKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let retired: Vec<_> = self.retired.iter().map(|(id, _)| id).collect();
+        f.debug_struct("SealingKeys")
+            .field("current", &self.current.0)
+            .field("retired", &retired)
+            .finish()
+    }
+}
@@ -0,0 +1,384 @@
+use std::{cmp::Ordering, collections::BTreeMap, sync::Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use dashmap::{mapref::entry::Entry, DashMap};
+
+use super::{StoreError, UrlStore};
+use crate::shortener::{
+    url_policy, ApiKey, Click, DayStats, DeadLetter, LinkCursor, LinkLimits, LinkQuery, LinkRecord,
+    LinkSort, LinkSummary, NewLink, RedirectStatus, Resolved, SortOrder, StatsRes, VariantStats,
+    Webhook,
+};
+
+/// Keeps everything in process memory, for local runs and tests.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    links: DashMap<String, Link>,
+    // plain links by (owner, url), so shortening the same url again returns the same id
+    permanent: DashMap<(String, String), String>,
+    clicks: DashMap<String, Vec<Click>>,
+    // api key ids by key hash
+    api_keys: DashMap<String, String>,
+    webhooks: DashMap<String, Webhook>,
+    dead_letters: Mutex<Vec<DeadLetter>>,
+}
+
+#[derive(Debug)]
+struct Link {
+    url: String,
+    limits: LinkLimits,
+    clicks: i32,
+    owner: Option<String>,
+    redirect: Option<RedirectStatus>,
+    preview: bool,
+    utm: Option<String>,
+    routing: Option<String>,
+    password: Option<String>,
+    // whether the link is deduplicated by url, see `NewLink::is_plain`
+    plain: bool,
+    created_at: DateTime<Utc>,
+}
+
+impl MemoryStore {
+    fn insert_link(&self, id: &str, link: &NewLink) -> Result<(), StoreError> {
+        match self.links.entry(id.to_string()) {
+            Entry::Occupied(_) => Err(StoreError::IdTaken(id.to_string())),
+            Entry::Vacant(entry) => {
+                entry.insert(Link {
+                    url: link.url.clone(),
+                    limits: link.limits,
+                    clicks: 0,
+                    owner: link.owner.clone(),
+                    redirect: link.redirect,
+                    preview: link.preview,
+                    utm: link.utm.clone(),
+                    routing: link.routing.clone(),
+                    password: link.password.clone(),
+                    plain: link.is_plain(),
+                    created_at: Utc::now(),
+                });
+                Ok(())
+            }
+        }
+    }
+
+    // anonymous links are keyed under the empty owner, like the sql stores
+    fn permanent_key(owner: Option<&str>, url: &str) -> (String, String) {
+        (owner.unwrap_or_default().to_string(), url.to_string())
+    }
+
+    // returns the url of `id` and whether it is plain, if `owner` owns it
+    fn owned(&self, id: &str, owner: &str) -> Result<(String, bool), StoreError> {
+        let link = self
+            .links
+            .get(id)
+            .ok_or_else(|| StoreError::NotFound(id.to_string()))?;
+        if link.owner.as_deref() != Some(owner) {
+            return Err(StoreError::NotOwner(id.to_string()));
+        }
+        Ok((link.url.clone(), link.plain))
+    }
+
+    fn summary(&self, id: &str, link: &Link) -> LinkSummary {
+        LinkSummary {
+            id: id.to_string(),
+            url: link.url.clone(),
+            created_at: link.created_at,
+            expires_at: link.limits.expires_at,
+            max_clicks: link.limits.max_clicks,
+            owner: link.owner.clone(),
+            redirect: link.redirect,
+            preview: link.preview,
+            utm: link.utm.clone(),
+            routing: link.routing.clone(),
+            protected: link.password.is_some(),
+            clicks: self.clicks.get(id).map_or(0, |c| c.len() as i64),
+            used_up: matches!(link.limits.max_clicks, Some(max_clicks) if link.clicks >= max_clicks),
+        }
+    }
+}
+
+#[async_trait]
+impl UrlStore for MemoryStore {
+    async fn shorten(&self, id: &str, link: &NewLink) -> Result<String, StoreError> {
+        if !link.is_plain() {
+            self.insert_link(id, link)?;
+            return Ok(id.to_string());
+        }
+        let key = Self::permanent_key(link.owner.as_deref(), &link.url);
+        match self.permanent.entry(key) {
+            Entry::Occupied(entry) => Ok(entry.get().clone()),
+            Entry::Vacant(entry) => {
+                self.insert_link(id, link)?;
+                entry.insert(id.to_string());
+                Ok(id.to_string())
+            }
+        }
+    }
+
+    async fn insert(&self, id: &str, link: &NewLink) -> Result<(), StoreError> {
+        if !link.is_plain() {
+            return self.insert_link(id, link);
+        }
+        let key = Self::permanent_key(link.owner.as_deref(), &link.url);
+        match self.permanent.entry(key) {
+            Entry::Occupied(_) => Err(StoreError::UrlTaken),
+            Entry::Vacant(entry) => {
+                self.insert_link(id, link)?;
+                entry.insert(id.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    async fn update_url(&self, id: &str, owner: &str, url: &str) -> Result<(), StoreError> {
+        let (old_url, plain) = self.owned(id, owner)?;
+        if old_url == url {
+            return Ok(());
+        }
+        if plain {
+            match self.permanent.entry(Self::permanent_key(Some(owner), url)) {
+                Entry::Occupied(_) => return Err(StoreError::UrlTaken),
+                Entry::Vacant(entry) => {
+                    entry.insert(id.to_string());
+                }
+            }
+            // a separate call: the old key may live in the shard locked above
+            self.permanent
+                .remove(&Self::permanent_key(Some(owner), &old_url));
+        }
+        if let Some(mut link) = self.links.get_mut(id) {
+            link.url = url.to_string();
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str, owner: &str) -> Result<(), StoreError> {
+        let (url, plain) = self.owned(id, owner)?;
+        if plain {
+            self.permanent
+                .remove_if(&Self::permanent_key(Some(owner), &url), |_, v| v == id);
+        }
+        self.links.remove(id);
+        self.clicks.remove(id);
+        self.webhooks
+            .retain(|_, webhook| webhook.link_id.as_deref() != Some(id));
+        Ok(())
+    }
+
+    async fn get_url(&self, id: &str, unlocked: bool) -> Result<Resolved, StoreError> {
+        // the entry stays locked while we check and count, like the sql stores' single UPDATE
+        let mut link = self
+            .links
+            .get_mut(id)
+            .ok_or_else(|| StoreError::NotFound(id.to_string()))?;
+        let expired =
+            matches!(link.limits.expires_at, Some(expires_at) if expires_at <= Utc::now());
+        let exhausted =
+            matches!(link.limits.max_clicks, Some(max_clicks) if link.clicks >= max_clicks);
+        if expired || exhausted {
+            return Ok(Resolved::Gone);
+        }
+        let protected = link.password.is_some();
+        if !protected || unlocked {
+            link.clicks += 1;
+        }
+        Ok(Resolved::Url {
+            url: link.url.clone(),
+            limits: link.limits,
+            redirect: link.redirect,
+            preview: link.preview,
+            utm: link.utm.clone(),
+            routing: link.routing.clone(),
+            protected,
+        })
+    }
+
+    async fn record_clicks(&self, clicks: &[Click]) -> Result<(), StoreError> {
+        // clicks on links deleted since are dropped, like the sql stores do
+        for click in clicks.iter().filter(|c| self.links.contains_key(&c.url_id)) {
+            self.clicks
+                .entry(click.url_id.clone())
+                .or_default()
+                .push(click.clone());
+        }
+        Ok(())
+    }
+
+    async fn stats(&self, id: &str) -> Result<StatsRes, StoreError> {
+        if !self.links.contains_key(id) {
+            return Err(StoreError::NotFound(id.to_string()));
+        }
+        let mut days = BTreeMap::new();
+        let mut variants = BTreeMap::new();
+        if let Some(clicks) = self.clicks.get(id) {
+            for click in clicks.iter() {
+                *days.entry(click.clicked_at.date_naive()).or_insert(0) += 1;
+                if let Some(variant) = &click.variant {
+                    *variants.entry(variant.clone()).or_insert(0) += 1;
+                }
+            }
+        }
+        let days = days
+            .into_iter()
+            .map(|(day, clicks)| DayStats { day, clicks })
+            .collect();
+        let variants = variants
+            .into_iter()
+            .map(|(variant, clicks)| VariantStats { variant, clicks })
+            .collect();
+        Ok(StatsRes::new(id, days, variants))
+    }
+
+    async fn export(&self) -> Result<Vec<LinkRecord>, StoreError> {
+        let mut links: Vec<_> = self
+            .links
+            .iter()
+            .map(|link| LinkRecord {
+                id: link.key().clone(),
+                url: link.url.clone(),
+                expires_at: link.limits.expires_at,
+                max_clicks: link.limits.max_clicks,
+                clicks: link.clicks,
+                owner: link.owner.clone(),
+                redirect: link.redirect,
+                preview: link.preview,
+                utm: link.utm.clone(),
+                routing: link.routing.clone(),
+                password_hash: link.password.clone(),
+            })
+            .collect();
+        links.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(links)
+    }
+
+    async fn find_link(&self, id: &str) -> Result<LinkSummary, StoreError> {
+        let link = self
+            .links
+            .get(id)
+            .ok_or_else(|| StoreError::NotFound(id.to_string()))?;
+        Ok(self.summary(id, &link))
+    }
+
+    async fn find_password(&self, id: &str) -> Result<Option<String>, StoreError> {
+        let link = self
+            .links
+            .get(id)
+            .ok_or_else(|| StoreError::NotFound(id.to_string()))?;
+        Ok(link.password.clone())
+    }
+
+    async fn list(&self, query: &LinkQuery) -> Result<Vec<LinkSummary>, StoreError> {
+        let search = query.search.as_deref().map(str::to_lowercase);
+        let mut links: Vec<_> = self
+            .links
+            .iter()
+            .filter(|link| {
+                search
+                    .as_deref()
+                    .is_none_or(|search| link.url.to_lowercase().contains(search))
+            })
+            .filter(|link| {
+                query
+                    .domain
+                    .as_deref()
+                    .is_none_or(|domain| on_domain(&link.url, domain))
+            })
+            .map(|link| self.summary(link.key(), &link))
+            .collect();
+        let cmp = |a: &LinkSummary, b: &LinkCursor| {
+            let ord = match query.sort {
+                LinkSort::CreatedAt => a.created_at.cmp(&b.created_at),
+                LinkSort::Clicks => a.clicks.cmp(&b.clicks),
+            }
+            .then_with(|| a.id.cmp(&b.id));
+            match query.order {
+                SortOrder::Asc => ord,
+                SortOrder::Desc => ord.reverse(),
+            }
+        };
+        if let Some(after) = &query.after {
+            links.retain(|link| cmp(link, after) == Ordering::Greater);
+        }
+        links.sort_by(|a, b| cmp(a, &LinkCursor::from(b)));
+        links.truncate(query.limit as usize);
+        Ok(links)
+    }
+
+    async fn create_webhook(&self, webhook: &Webhook) -> Result<(), StoreError> {
+        self.webhooks.insert(webhook.id.clone(), webhook.clone());
+        Ok(())
+    }
+
+    async fn list_webhooks(&self, owner: &str) -> Result<Vec<Webhook>, StoreError> {
+        let mut webhooks: Vec<_> = self
+            .webhooks
+            .iter()
+            .filter(|webhook| webhook.owner == owner)
+            .map(|webhook| webhook.clone())
+            .collect();
+        webhooks.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        Ok(webhooks)
+    }
+
+    async fn delete_webhook(&self, id: &str, owner: &str) -> Result<(), StoreError> {
+        self.webhooks
+            .remove_if(id, |_, webhook| webhook.owner == owner)
+            .map(|_| ())
+            .ok_or_else(|| StoreError::WebhookNotFound(id.to_string()))
+    }
+
+    async fn find_webhooks(&self, link_id: &str) -> Result<Vec<Webhook>, StoreError> {
+        let owner = self.links.get(link_id).and_then(|link| link.owner.clone());
+        let mut webhooks: Vec<_> = self
+            .webhooks
+            .iter()
+            .filter(|webhook| match &webhook.link_id {
+                Some(id) => id == link_id,
+                None => owner.as_deref() == Some(webhook.owner.as_str()),
+            })
+            .map(|webhook| webhook.clone())
+            .collect();
+        webhooks.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        Ok(webhooks)
+    }
+
+    async fn insert_dead_letter(&self, dead_letter: &DeadLetter) -> Result<(), StoreError> {
+        self.dead_letters
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(dead_letter.clone());
+        Ok(())
+    }
+
+    async fn dead_letters(&self, limit: u32) -> Result<Vec<DeadLetter>, StoreError> {
+        let dead_letters = self.dead_letters.lock().unwrap_or_else(|e| e.into_inner());
+        // stored in the order they failed
+        Ok(dead_letters
+            .iter()
+            .rev()
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn create_api_key(&self, key: &ApiKey) -> Result<(), StoreError> {
+        self.api_keys.insert(key.key_hash.clone(), key.id.clone());
+        Ok(())
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<String>, StoreError> {
+        Ok(self.api_keys.get(key_hash).map(|id| id.clone()))
+    }
+}
+
+// whether the url's host is `domain` or one of its subdomains
+fn on_domain(url: &str, domain: &str) -> bool {
+    url_policy::host(url).is_some_and(|host| {
+        host == domain
+            || host
+                .strip_suffix(domain)
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+
+/// The schemas of the sql stores, embedded at compile time.
+pub(super) static POSTGRES: Migrator = sqlx::migrate!("./migrations/postgres");
+pub(super) static SQLITE: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// applied, but the embedded sql changed since, the store refuses to migrate
+    Modified,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+/// Every embedded migration, checked against the ones applied to the database.
+pub(super) async fn status(
+    migrator: &Migrator,
+    conn: &mut impl Migrate,
+) -> Result<Vec<MigrationStatus>, MigrateError> {
+    conn.ensure_migrations_table().await?;
+    let applied: HashMap<_, _> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| (m.version, m.checksum))
+        .collect();
+    let status = migrator
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| {
+            let state = match applied.get(&m.version) {
+                None => MigrationState::Pending,
+                Some(checksum) if *checksum == m.checksum => MigrationState::Applied,
+                Some(_) => MigrationState::Modified,
+            };
+            MigrationStatus {
+                version: m.version,
+                description: m.description.to_string(),
+                state,
+            }
+        })
+        .collect();
+    Ok(status)
+}
+
+/// The migrations a rollback reverted, from the status before and after it.
+pub(super) fn reverted(
+    before: Vec<MigrationStatus>,
+    after: &[MigrationStatus],
+) -> Vec<MigrationStatus> {
+    before
+        .into_iter()
+        .filter(|m| m.state != MigrationState::Pending)
+        .filter(|m| {
+            after
+                .iter()
+                .any(|a| a.version == m.version && a.state == MigrationState::Pending)
+        })
+        .rev()
+        .collect()
+}
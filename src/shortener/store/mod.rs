@@ -0,0 +1,166 @@
+mod memory;
+mod migrations;
+mod postgres;
+mod sqlite;
+
+pub use memory::MemoryStore;
+pub use migrations::{MigrationState, MigrationStatus};
+pub use postgres::PgStore;
+pub use sqlite::SqliteStore;
+
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+use thiserror::Error;
+
+use crate::health::{PoolStats, Probe};
+
+use super::{
+    ApiKey, Click, DeadLetter, LinkQuery, LinkRecord, LinkSummary, NewLink, Resolved, StatsRes,
+    Webhook,
+};
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("id {0} is already taken")]
+    IdTaken(String),
+    #[error("url is already shortened under another id")]
+    UrlTaken,
+    #[error("link {0} not found")]
+    NotFound(String),
+    #[error("link {0} belongs to another api key")]
+    NotOwner(String),
+    #[error("webhook {0} not found")]
+    WebhookNotFound(String),
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migrate(#[from] sqlx::migrate::MigrateError),
+}
+
+/// Where the shortener keeps its links and clicks.
+#[async_trait]
+pub trait UrlStore: fmt::Debug + Send + Sync {
+    /// Stores the link under `id` and returns the id of the link. A plain link
+    /// for a url the owner already shortened keeps (and returns) its existing id.
+    async fn shorten(&self, id: &str, link: &NewLink) -> Result<String, StoreError>;
+
+    /// Stores the link under exactly `id`, failing if the id is taken, or the url
+    /// is for a plain link the owner already has.
+    async fn insert(&self, id: &str, link: &NewLink) -> Result<(), StoreError>;
+
+    /// Points an owned link at a new url.
+    async fn update_url(&self, id: &str, owner: &str, url: &str) -> Result<(), StoreError>;
+
+    /// Deletes an owned link along with its clicks.
+    async fn delete(&self, id: &str, owner: &str) -> Result<(), StoreError>;
+
+    /// Resolves `id`, counting the visit against the link's limits. Visits of a
+    /// password protected link only count once the visitor has `unlocked` it.
+    async fn get_url(&self, id: &str, unlocked: bool) -> Result<Resolved, StoreError>;
+
+    /// Records the clicks, skipping those of links deleted since.
+    async fn record_clicks(&self, clicks: &[Click]) -> Result<(), StoreError>;
+
+    async fn stats(&self, id: &str) -> Result<StatsRes, StoreError>;
+
+    /// Every stored link, ordered by id.
+    async fn export(&self) -> Result<Vec<LinkRecord>, StoreError>;
+
+    /// Looks up a link without counting a visit.
+    async fn find_link(&self, id: &str) -> Result<LinkSummary, StoreError>;
+
+    /// The password hash of link `id`, `None` if it isn't protected.
+    async fn find_password(&self, id: &str) -> Result<Option<String>, StoreError>;
+
+    /// A page of links matching the query, in its sort order.
+    async fn list(&self, query: &LinkQuery) -> Result<Vec<LinkSummary>, StoreError>;
+
+    async fn create_api_key(&self, key: &ApiKey) -> Result<(), StoreError>;
+
+    /// Returns the id of the api key with the given hash.
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<String>, StoreError>;
+
+    async fn create_webhook(&self, webhook: &Webhook) -> Result<(), StoreError>;
+
+    /// The webhooks registered with an api key, oldest first.
+    async fn list_webhooks(&self, owner: &str) -> Result<Vec<Webhook>, StoreError>;
+
+    /// Deletes a webhook registered with `owner`, others are reported as not found.
+    async fn delete_webhook(&self, id: &str, owner: &str) -> Result<(), StoreError>;
+
+    /// The webhooks notified of clicks on `link_id`: its own and its owner's.
+    async fn find_webhooks(&self, link_id: &str) -> Result<Vec<Webhook>, StoreError>;
+
+    async fn insert_dead_letter(&self, dead_letter: &DeadLetter) -> Result<(), StoreError>;
+
+    /// The latest dead letters, newest first.
+    async fn dead_letters(&self, limit: u32) -> Result<Vec<DeadLetter>, StoreError>;
+
+    /// Fails if the store can't serve requests right now.
+    async fn ping(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Connections of the store's pool, `None` for stores without one.
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+
+    /// Applies pending schema migrations.
+    async fn migrate(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Reverts the applied migrations newer than `target` (0 reverts all of them),
+    /// returning them newest first.
+    async fn rollback(&self, _target: i64) -> Result<Vec<MigrationStatus>, StoreError> {
+        Ok(vec![])
+    }
+
+    /// Every migration the store knows about, oldest first. Stores without a
+    /// schema have none.
+    async fn migrations(&self) -> Result<Vec<MigrationStatus>, StoreError> {
+        Ok(vec![])
+    }
+}
+
+// `/readyz` checks the store, `/metrics` reports its pool
+#[async_trait]
+impl Probe for Arc<dyn UrlStore> {
+    async fn check(&self) -> anyhow::Result<()> {
+        Ok(self.ping().await?)
+    }
+
+    fn pool(&self) -> Option<PoolStats> {
+        self.pool_stats()
+    }
+}
+
+// escapes `%`, `_` and `\` so user input only matches literally in `LIKE ... ESCAPE '\'`
+fn like_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Opens the store the url points at: `memory`, `sqlite:...` or `postgres://...`,
+/// applying pending migrations if asked to.
+pub async fn open_store(url: &str, migrate: bool) -> anyhow::Result<Arc<dyn UrlStore>> {
+    let store: Arc<dyn UrlStore> = if url == "memory" {
+        Arc::new(MemoryStore::default())
+    } else if url.starts_with("sqlite:") {
+        Arc::new(SqliteStore::connect(url).await?)
+    } else {
+        Arc::new(PgStore::connect(url).await?)
+    };
+    if migrate {
+        store.migrate().await?;
+    }
+    Ok(store)
+}
@@ -0,0 +1,486 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+
+use super::{
+    like_escape,
+    migrations::{self, POSTGRES},
+    MigrationStatus, StoreError, UrlStore,
+};
+use crate::health::PoolStats;
+use crate::shortener::{
+    ApiKey, Click, DayStats, DeadLetter, LinkLimits, LinkQuery, LinkRecord, LinkSort, LinkSummary,
+    NewLink, RedirectStatus, Resolved, StatsRes, VariantStats, Webhook,
+};
+
+// a link as the admin api sees it, clicks counted from the clicks table
+const LINK_SUMMARY: &str = r#"
+  SELECT id, url, created_at, expires_at, max_clicks, NULLIF(owner, '') AS owner, redirect,
+      preview, utm, routing, password_hash IS NOT NULL AS protected,
+      (SELECT count(*) FROM clicks WHERE url_id = urls.id) AS clicks,
+      COALESCE(urls.clicks >= max_clicks, FALSE) AS used_up
+  FROM urls
+"#;
+
+const WEBHOOK: &str = "SELECT id, owner, link_id, url, secret, created_at FROM webhooks";
+
+#[derive(Debug, Clone)]
+pub struct PgStore {
+    db: PgPool,
+}
+
+#[derive(Debug, FromRow)]
+struct UrlRecord {
+    #[sqlx(default)]
+    id: String,
+    #[sqlx(default)]
+    url: String,
+    #[sqlx(default)]
+    expires_at: Option<DateTime<Utc>>,
+    #[sqlx(default)]
+    max_clicks: Option<i32>,
+    #[sqlx(default)]
+    redirect: Option<RedirectStatus>,
+    #[sqlx(default)]
+    preview: bool,
+    #[sqlx(default)]
+    utm: Option<String>,
+    #[sqlx(default)]
+    routing: Option<String>,
+    #[sqlx(default)]
+    protected: bool,
+}
+
+impl PgStore {
+    /// Connects and applies pending migrations.
+    pub async fn try_new(url: &str) -> Result<Self> {
+        let store = Self::connect(url).await?;
+        store.migrate().await?;
+        Ok(store)
+    }
+
+    /// Connects without touching the schema.
+    pub async fn connect(url: &str) -> Result<Self> {
+        let pool = PgPool::connect(url).await?;
+        Ok(Self { db: pool })
+    }
+
+    // why an owner-scoped statement touched no rows
+    async fn not_owned(&self, id: &str) -> StoreError {
+        let exists = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM urls WHERE id = $1)")
+            .bind(id)
+            .fetch_one(&self.db)
+            .await;
+        match exists {
+            Ok(true) => StoreError::NotOwner(id.to_string()),
+            Ok(false) => StoreError::NotFound(id.to_string()),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl UrlStore for PgStore {
+    async fn shorten(&self, id: &str, link: &NewLink) -> Result<String, StoreError> {
+        let ret: UrlRecord = sqlx::query_as(
+            r#"
+          INSERT INTO urls (
+              id, url, expires_at, max_clicks, owner, redirect, preview, utm, routing, password_hash,
+              aliased
+          )
+          VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+          ON CONFLICT (url, owner)
+              WHERE expires_at IS NULL AND max_clicks IS NULL AND redirect IS NULL
+                  AND NOT preview AND utm IS NULL AND routing IS NULL AND password_hash IS NULL
+                  AND NOT aliased
+          DO UPDATE SET url=EXCLUDED.url RETURNING id
+          "#,
+        )
+        .bind(id)
+        .bind(&link.url)
+        .bind(link.limits.expires_at)
+        .bind(link.limits.max_clicks)
+        .bind(link.owner.as_deref().unwrap_or_default())
+        .bind(link.redirect)
+        .bind(link.preview)
+        .bind(&link.utm)
+        .bind(&link.routing)
+        .bind(&link.password)
+        .bind(link.aliased)
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| map_unique_violation(e, id))?;
+        Ok(ret.id)
+    }
+
+    async fn insert(&self, id: &str, link: &NewLink) -> Result<(), StoreError> {
+        sqlx::query(
+            r#"
+          INSERT INTO urls (
+              id, url, expires_at, max_clicks, owner, redirect, preview, utm, routing, password_hash,
+              aliased
+          )
+          VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+          "#,
+        )
+        .bind(id)
+        .bind(&link.url)
+        .bind(link.limits.expires_at)
+        .bind(link.limits.max_clicks)
+        .bind(link.owner.as_deref().unwrap_or_default())
+        .bind(link.redirect)
+        .bind(link.preview)
+        .bind(&link.utm)
+        .bind(&link.routing)
+        .bind(&link.password)
+        .bind(link.aliased)
+        .execute(&self.db)
+        .await
+        .map_err(|e| map_unique_violation(e, id))?;
+        Ok(())
+    }
+
+    async fn update_url(&self, id: &str, owner: &str, url: &str) -> Result<(), StoreError> {
+        let ret = sqlx::query("UPDATE urls SET url = $1 WHERE id = $2 AND owner = $3")
+            .bind(url)
+            .bind(id)
+            .bind(owner)
+            .execute(&self.db)
+            .await
+            .map_err(|e| map_unique_violation(e, id))?;
+        if ret.rows_affected() == 0 {
+            return Err(self.not_owned(id).await);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str, owner: &str) -> Result<(), StoreError> {
+        let ret = sqlx::query("DELETE FROM urls WHERE id = $1 AND owner = $2")
+            .bind(id)
+            .bind(owner)
+            .execute(&self.db)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(self.not_owned(id).await);
+        }
+        Ok(())
+    }
+
+    async fn get_url(&self, id: &str, unlocked: bool) -> Result<Resolved, StoreError> {
+        // count the click and check the limits in one statement so concurrent
+        // redirects can't overshoot max_clicks
+        let ret: Option<UrlRecord> = sqlx::query_as(
+            r#"
+          UPDATE urls SET clicks = clicks + CASE WHEN password_hash IS NULL OR $2 THEN 1 ELSE 0 END
+          WHERE id = $1
+              AND (expires_at IS NULL OR expires_at > now())
+              AND (max_clicks IS NULL OR clicks < max_clicks)
+          RETURNING url, expires_at, max_clicks, redirect, preview, utm, routing,
+              password_hash IS NOT NULL AS protected
+          "#,
+        )
+        .bind(id)
+        .bind(unlocked)
+        .fetch_optional(&self.db)
+        .await?;
+        if let Some(ret) = ret {
+            let limits = LinkLimits {
+                expires_at: ret.expires_at,
+                max_clicks: ret.max_clicks,
+            };
+            return Ok(Resolved::Url {
+                url: ret.url,
+                limits,
+                redirect: ret.redirect,
+                preview: ret.preview,
+                utm: ret.utm,
+                routing: ret.routing,
+                protected: ret.protected,
+            });
+        }
+        // nothing updated: either the link is unknown or it is no longer usable
+        let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM urls WHERE id = $1)")
+            .bind(id)
+            .fetch_one(&self.db)
+            .await?;
+        if exists {
+            Ok(Resolved::Gone)
+        } else {
+            Err(StoreError::NotFound(id.to_string()))
+        }
+    }
+
+    async fn record_clicks(&self, clicks: &[Click]) -> Result<(), StoreError> {
+        // clicks on links deleted since are dropped instead of failing the batch
+        let mut query: QueryBuilder<Postgres> = QueryBuilder::new(
+            r#"
+          INSERT INTO clicks (url_id, clicked_at, referrer, user_agent, ip, variant)
+          SELECT c.url_id, c.clicked_at, c.referrer, c.user_agent, c.ip, c.variant FROM (
+          "#,
+        );
+        query.push_values(clicks, |mut row, click| {
+            row.push_bind(&click.url_id)
+                .push_bind(click.clicked_at)
+                .push_bind(&click.referrer)
+                .push_bind(&click.user_agent)
+                .push_bind(&click.ip)
+                .push_bind(&click.variant);
+        });
+        query.push(
+            r#"
+          ) AS c (url_id, clicked_at, referrer, user_agent, ip, variant)
+          WHERE EXISTS (SELECT 1 FROM urls WHERE urls.id = c.url_id)
+          "#,
+        );
+        query.build().execute(&self.db).await?;
+        Ok(())
+    }
+
+    async fn stats(&self, id: &str) -> Result<StatsRes, StoreError> {
+        // make sure unknown ids are reported as such instead of empty stats
+        let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM urls WHERE id = $1)")
+            .bind(id)
+            .fetch_one(&self.db)
+            .await?;
+        if !exists {
+            return Err(StoreError::NotFound(id.to_string()));
+        }
+        let days: Vec<DayStats> = sqlx::query_as(
+            r#"
+          SELECT (clicked_at AT TIME ZONE 'UTC')::date AS day, count(*) AS clicks
+          FROM clicks WHERE url_id = $1
+          GROUP BY day ORDER BY day
+          "#,
+        )
+        .bind(id)
+        .fetch_all(&self.db)
+        .await?;
+        let variants: Vec<VariantStats> = sqlx::query_as(
+            r#"
+          SELECT variant, count(*) AS clicks
+          FROM clicks WHERE url_id = $1 AND variant IS NOT NULL
+          GROUP BY variant ORDER BY variant
+          "#,
+        )
+        .bind(id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(StatsRes::new(id, days, variants))
+    }
+
+    async fn export(&self) -> Result<Vec<LinkRecord>, StoreError> {
+        let links = sqlx::query_as(
+            r#"
+          SELECT id, url, expires_at, max_clicks, clicks, NULLIF(owner, '') AS owner, redirect,
+              preview, utm, routing, password_hash
+          FROM urls ORDER BY id
+          "#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+        Ok(links)
+    }
+
+    async fn find_link(&self, id: &str) -> Result<LinkSummary, StoreError> {
+        sqlx::query_as(&format!("{LINK_SUMMARY} WHERE id = $1"))
+            .bind(id)
+            .fetch_optional(&self.db)
+            .await?
+            .ok_or_else(|| StoreError::NotFound(id.to_string()))
+    }
+
+    async fn find_password(&self, id: &str) -> Result<Option<String>, StoreError> {
+        let ret: Option<Option<String>> =
+            sqlx::query_scalar("SELECT password_hash FROM urls WHERE id = $1")
+                .bind(id)
+                .fetch_optional(&self.db)
+                .await?;
+        ret.ok_or_else(|| StoreError::NotFound(id.to_string()))
+    }
+
+    async fn list(&self, query: &LinkQuery) -> Result<Vec<LinkSummary>, StoreError> {
+        // normalized urls are `scheme://host[:port]` followed by `/`, `?`, `#` or nothing
+        let mut sql: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+            r#"
+          SELECT * FROM (
+              SELECT *, substring(url FROM '://([^/?#:]+)') AS host FROM ({LINK_SUMMARY}) urls
+          ) links WHERE TRUE
+          "#
+        ));
+        if let Some(search) = &query.search {
+            sql.push(" AND url ILIKE ")
+                .push_bind(format!("%{}%", like_escape(search)))
+                .push(r" ESCAPE '\'");
+        }
+        if let Some(domain) = &query.domain {
+            sql.push(" AND (host = ")
+                .push_bind(domain)
+                .push(" OR host LIKE ")
+                .push_bind(format!("%.{}", like_escape(domain)))
+                .push(r" ESCAPE '\')");
+        }
+        let (column, order) = (query.sort.column(), query.order.keyword());
+        if let Some(after) = &query.after {
+            sql.push(format_args!(
+                " AND ({column}, id) {} (",
+                query.order.after()
+            ));
+            match query.sort {
+                LinkSort::CreatedAt => sql.push_bind(after.created_at),
+                LinkSort::Clicks => sql.push_bind(after.clicks),
+            };
+            sql.push(", ").push_bind(&after.id).push(")");
+        }
+        sql.push(format_args!(
+            " ORDER BY {column} {order}, id {order} LIMIT "
+        ))
+        .push_bind(i64::from(query.limit));
+        Ok(sql.build_query_as().fetch_all(&self.db).await?)
+    }
+
+    async fn create_webhook(&self, webhook: &Webhook) -> Result<(), StoreError> {
+        sqlx::query(
+            r#"
+          INSERT INTO webhooks (id, owner, link_id, url, secret, created_at)
+          VALUES ($1, $2, $3, $4, $5, $6)
+          "#,
+        )
+        .bind(&webhook.id)
+        .bind(&webhook.owner)
+        .bind(&webhook.link_id)
+        .bind(&webhook.url)
+        .bind(&webhook.secret)
+        .bind(webhook.created_at)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn list_webhooks(&self, owner: &str) -> Result<Vec<Webhook>, StoreError> {
+        let webhooks = sqlx::query_as(&format!(
+            "{WEBHOOK} WHERE owner = $1 ORDER BY created_at, id"
+        ))
+        .bind(owner)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(webhooks)
+    }
+
+    async fn delete_webhook(&self, id: &str, owner: &str) -> Result<(), StoreError> {
+        let ret = sqlx::query("DELETE FROM webhooks WHERE id = $1 AND owner = $2")
+            .bind(id)
+            .bind(owner)
+            .execute(&self.db)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(StoreError::WebhookNotFound(id.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn find_webhooks(&self, link_id: &str) -> Result<Vec<Webhook>, StoreError> {
+        // webhooks always have an owner, anonymous links ('' owner) match none of theirs
+        let webhooks = sqlx::query_as(&format!(
+            r#"
+          {WEBHOOK}
+          WHERE link_id = $1
+              OR (link_id IS NULL AND owner = (SELECT owner FROM urls WHERE id = $1))
+          ORDER BY created_at, id
+          "#
+        ))
+        .bind(link_id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(webhooks)
+    }
+
+    async fn insert_dead_letter(&self, dead_letter: &DeadLetter) -> Result<(), StoreError> {
+        sqlx::query(
+            r#"
+          INSERT INTO webhook_dead_letters (webhook_id, url, payload, attempts, error, failed_at)
+          VALUES ($1, $2, $3, $4, $5, $6)
+          "#,
+        )
+        .bind(&dead_letter.webhook_id)
+        .bind(&dead_letter.url)
+        .bind(&dead_letter.payload)
+        .bind(dead_letter.attempts)
+        .bind(&dead_letter.error)
+        .bind(dead_letter.failed_at)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn dead_letters(&self, limit: u32) -> Result<Vec<DeadLetter>, StoreError> {
+        let dead_letters = sqlx::query_as(
+            r#"
+          SELECT webhook_id, url, payload, attempts, error, failed_at
+          FROM webhook_dead_letters ORDER BY failed_at DESC, id DESC LIMIT $1
+          "#,
+        )
+        .bind(i64::from(limit))
+        .fetch_all(&self.db)
+        .await?;
+        Ok(dead_letters)
+    }
+
+    async fn create_api_key(&self, key: &ApiKey) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT INTO api_keys (id, key_hash, name, created_at) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(&key.id)
+        .bind(&key.key_hash)
+        .bind(&key.name)
+        .bind(key.created_at)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<String>, StoreError> {
+        let id = sqlx::query_scalar("SELECT id FROM api_keys WHERE key_hash = $1")
+            .bind(key_hash)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(id)
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        sqlx::query("SELECT 1").execute(&self.db).await?;
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(PoolStats::of(&self.db))
+    }
+
+    async fn migrate(&self) -> Result<(), StoreError> {
+        POSTGRES.run(&self.db).await?;
+        Ok(())
+    }
+
+    async fn rollback(&self, target: i64) -> Result<Vec<MigrationStatus>, StoreError> {
+        let before = self.migrations().await?;
+        POSTGRES.undo(&self.db, target).await?;
+        Ok(migrations::reverted(before, &self.migrations().await?))
+    }
+
+    async fn migrations(&self) -> Result<Vec<MigrationStatus>, StoreError> {
+        let mut conn = self.db.acquire().await?;
+        Ok(migrations::status(&POSTGRES, &mut *conn).await?)
+    }
+}
+
+fn map_unique_violation(e: sqlx::Error, id: &str) -> StoreError {
+    let constraint = e
+        .as_database_error()
+        .filter(|db_err| db_err.is_unique_violation())
+        .and_then(|db_err| db_err.constraint());
+    match constraint {
+        Some("urls_pkey") => StoreError::IdTaken(id.to_string()),
+        Some("urls_permanent_url_owner_key") => StoreError::UrlTaken,
+        _ => e.into(),
+    }
+}
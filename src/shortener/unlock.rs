@@ -0,0 +1,217 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    extract::{Path, RawQuery, State},
+    response::{Html, IntoResponse, Response},
+};
+use chrono::Utc;
+use http::{
+    header::{CACHE_CONTROL, COOKIE, LOCATION, SET_COOKIE},
+    HeaderMap, HeaderValue, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{preview::escape, AppError, AppForm, AppState, ProblemDetails};
+
+const PASSWORD_MAX_LEN: usize = 128;
+// how long a visitor who entered the password can follow the link, in seconds
+const UNLOCK_TTL: i64 = 60 * 60;
+
+/// The password form of a protected link.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UnlockReq {
+    pub password: String,
+}
+
+/// Checks the password and unlocks the link for a while with a signed cookie,
+/// sending the visitor back to the short url they came from. Submissions are
+/// rate limited per client, see [`super::RateLimits`].
+#[utoipa::path(
+    post,
+    path = "/{id}",
+    tag = "links",
+    params(("id" = String, Path, description = "id or alias of the link")),
+    request_body(
+        content = UnlockReq,
+        content_type = "application/x-www-form-urlencoded",
+        example = json!({"password": "hunter2"})
+    ),
+    responses(
+        (status = 303, description = "Unlocked, back to the short url",
+            headers(
+                ("location" = String, description = "the short url"),
+                ("set-cookie" = String, description = "unlocks the link for an hour"),
+            )),
+        (status = 400, description = "Malformed form", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Wrong password, the form again", body = String, content_type = "text/html"),
+        (status = 404, description = "No such link", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Missing password", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub(super) async fn unlock(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    RawQuery(query): RawQuery,
+    AppForm(req): AppForm<UnlockReq>,
+) -> Result<Response, AppError> {
+    // the form of a preview posts to `/:id+`
+    let link_id = id.strip_suffix('+').unwrap_or(&id);
+    let password_hash = state
+        .store
+        .find_password(link_id)
+        .await
+        .map_err(AppError::store(format!("failed to find {link_id}")))?;
+    let mut headers = HeaderMap::new();
+    if let Some(password_hash) = password_hash {
+        if !verify_password(req.password, password_hash).await? {
+            return Ok(form(&state, link_id, true));
+        }
+        let cookie = state
+            .unlock_cookie(link_id)
+            .parse()
+            .map_err(|e| AppError::internal(format!("invalid unlock cookie for {link_id}"), e))?;
+        headers.insert(SET_COOKIE, cookie);
+    }
+    let mut location = state.short_url(&id);
+    if let Some(query) = query {
+        location.push('?');
+        location.push_str(&query);
+    }
+    let location = location
+        .parse()
+        .map_err(|e| AppError::internal(format!("invalid short url for {id}"), e))?;
+    headers.insert(LOCATION, location);
+    Ok((StatusCode::SEE_OTHER, headers).into_response())
+}
+
+/// The page shown instead of a protected link until it is unlocked. It posts
+/// back to the url it was shown at, so the query string is kept.
+pub(super) fn form(state: &AppState, id: &str, wrong: bool) -> Response {
+    let short_url = escape(&state.short_url(id));
+    let (status, error) = if wrong {
+        (
+            StatusCode::FORBIDDEN,
+            r#"<p class="error">Wrong password, try again.</p>"#,
+        )
+    } else {
+        (StatusCode::OK, "")
+    };
+    let page = Html(format!(
+        r#"<!doctype html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>{short_url} is password protected</title>
+<style>
+body {{ font-family: system-ui, sans-serif; max-width: 40rem; margin: 4rem auto; padding: 0 1rem; line-height: 1.5; }}
+.error {{ background: #f8d7da; border: 1px solid #f1aeb5; padding: 0.75rem 1rem; }}
+</style>
+</head>
+<body>
+<h1>{short_url} is password protected</h1>
+{error}
+<form method="post">
+<label>Password <input type="password" name="password" required autofocus></label>
+<button type="submit">Continue</button>
+</form>
+</body>
+</html>
+"#
+    ));
+    let cache = [(CACHE_CONTROL, HeaderValue::from_static("private, no-store"))];
+    (status, cache, page).into_response()
+}
+
+/// Whether the request carries a valid, unexpired unlock cookie for link `id`.
+pub(super) fn is_unlocked(state: &AppState, id: &str, headers: &HeaderMap) -> bool {
+    let name = cookie_name(id);
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .filter(|(key, _)| *key == name)
+        .any(|(_, value)| {
+            let Some((expires, signature)) = value.split_once('.') else {
+                return false;
+            };
+            let Ok(signature) = blake3::Hash::from_hex(signature) else {
+                return false;
+            };
+            // blake3::Hash compares in constant time
+            expires
+                .parse::<i64>()
+                .is_ok_and(|e| e > Utc::now().timestamp())
+                && signature == sign(&state.cookie_key, id, expires)
+        })
+}
+
+impl AppState {
+    // `Set-Cookie` unlocking link `id` for `UNLOCK_TTL`
+    fn unlock_cookie(&self, id: &str) -> String {
+        let expires = (Utc::now().timestamp() + UNLOCK_TTL).to_string();
+        let signature = sign(&self.cookie_key, id, &expires);
+        // a preview (`/:id+`) isn't under `/:id`, so the cookie is sent everywhere
+        let secure = if self.base_url.starts_with("https://") {
+            "; Secure"
+        } else {
+            ""
+        };
+        format!(
+            "{}={expires}.{signature}; Path=/; Max-Age={UNLOCK_TTL}; HttpOnly; SameSite=Lax{secure}",
+            cookie_name(id)
+        )
+    }
+}
+
+/// Hashes a new link's password with argon2 and a random salt.
+pub(super) async fn hash_password(password: &str) -> Result<String, AppError> {
+    validate_password(password).map_err(|e| AppError::Validation(e.to_string()))?;
+    let password = password.to_string();
+    // argon2 is slow on purpose, keep it off the async workers
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| AppError::internal("failed to hash password", anyhow::anyhow!(e)))
+    })
+    .await
+    .map_err(|e| AppError::internal("failed to hash password", e))?
+}
+
+async fn verify_password(password: String, password_hash: String) -> Result<bool, AppError> {
+    tokio::task::spawn_blocking(move || {
+        let hash = PasswordHash::new(&password_hash)
+            .map_err(|e| AppError::internal("invalid password hash", anyhow::anyhow!(e)))?;
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok())
+    })
+    .await
+    .map_err(|e| AppError::internal("failed to verify password", e))?
+}
+
+fn validate_password(password: &str) -> Result<(), &'static str> {
+    if password.is_empty() {
+        return Err("password must not be empty");
+    }
+    if password.len() > PASSWORD_MAX_LEN {
+        return Err("password must be at most 128 bytes");
+    }
+    Ok(())
+}
+
+fn cookie_name(id: &str) -> String {
+    format!("unlock_{id}")
+}
+
+fn sign(key: &[u8; 32], id: &str, expires: &str) -> blake3::Hash {
+    blake3::keyed_hash(key, format!("{id}.{expires}").as_bytes())
+}
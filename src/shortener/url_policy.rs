@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+
+use http::Uri;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum UrlError {
+    #[error("url is longer than {0} characters")]
+    TooLong(usize),
+    #[error("url is invalid: {0}")]
+    Invalid(#[from] http::uri::InvalidUri),
+    #[error("url scheme must be one of {0}")]
+    Scheme(String),
+    #[error("url must have a host")]
+    MissingHost,
+    #[error("url fragment contains invalid characters")]
+    Fragment,
+    #[error("url must not contain credentials")]
+    Credentials,
+    #[error("domain {0} is blocked")]
+    Blocked(String),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FragmentPolicy {
+    /// keep `#fragment`, single page apps route with it
+    #[default]
+    Keep,
+    Strip,
+}
+
+/// What the shortener accepts as a target url, and how it normalizes it.
+#[derive(Debug, Clone)]
+pub struct UrlPolicy {
+    pub max_length: usize,
+    pub schemes: Vec<String>,
+    /// blocked domains, subdomains included
+    pub blocklist: HashSet<String>,
+    /// known domains, subdomains included, previews of links elsewhere carry a warning
+    pub trusted: HashSet<String>,
+    pub fragment: FragmentPolicy,
+}
+
+impl Default for UrlPolicy {
+    fn default() -> Self {
+        Self {
+            max_length: 2048,
+            schemes: vec!["http".to_string(), "https".to_string()],
+            blocklist: HashSet::new(),
+            trusted: HashSet::new(),
+            fragment: FragmentPolicy::default(),
+        }
+    }
+}
+
+impl UrlPolicy {
+    pub fn block(mut self, domain: impl AsRef<str>) -> Self {
+        self.blocklist
+            .insert(domain.as_ref().trim_matches('.').to_ascii_lowercase());
+        self
+    }
+
+    pub fn trust(mut self, domain: impl AsRef<str>) -> Self {
+        self.trusted
+            .insert(domain.as_ref().trim_matches('.').to_ascii_lowercase());
+        self
+    }
+
+    /// Whether the host of a (normalized) url is on a trusted domain.
+    pub fn is_trusted(&self, url: &str) -> bool {
+        host(url).is_some_and(|host| on_domains(&self.trusted, &host))
+    }
+
+    /// Validates `url` and returns its normalized form, so equivalent urls are
+    /// stored (and deduplicated) as one: lowercased scheme and host, no default
+    /// port, `/` for an empty path.
+    pub fn normalize(&self, url: &str) -> Result<String, UrlError> {
+        let url = url.trim();
+        if url.len() > self.max_length {
+            return Err(UrlError::TooLong(self.max_length));
+        }
+        // http::Uri silently drops fragments, so split it off first
+        let (url, fragment) = match url.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (url, None),
+        };
+        let uri: Uri = url.parse()?;
+
+        let scheme = uri.scheme_str().unwrap_or_default().to_ascii_lowercase();
+        if !self.schemes.contains(&scheme) {
+            return Err(UrlError::Scheme(self.schemes.join(", ")));
+        }
+        let authority = uri.authority().ok_or(UrlError::MissingHost)?;
+        if authority.as_str().contains('@') {
+            return Err(UrlError::Credentials);
+        }
+        let host = authority.host().trim_end_matches('.').to_ascii_lowercase();
+        if host.is_empty() {
+            return Err(UrlError::MissingHost);
+        }
+        if on_domains(&self.blocklist, &host) {
+            return Err(UrlError::Blocked(host));
+        }
+
+        let port = match (scheme.as_str(), uri.port_u16()) {
+            ("http", Some(80)) | ("https", Some(443)) | (_, None) => String::new(),
+            (_, Some(port)) => format!(":{port}"),
+        };
+        let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        let mut normalized = format!("{scheme}://{host}{port}{path}");
+        if let Some(fragment) = fragment.filter(|f| !f.is_empty()) {
+            if !fragment.chars().all(|c| c.is_ascii_graphic()) {
+                return Err(UrlError::Fragment);
+            }
+            if self.fragment == FragmentPolicy::Keep {
+                normalized.push('#');
+                normalized.push_str(fragment);
+            }
+        }
+        Ok(normalized)
+    }
+}
+
+/// The lowercased host of a url, if it has one.
+pub(crate) fn host(url: &str) -> Option<String> {
+    // http::Uri rejects some fragments a normalized url may keep
+    let url = url.split_once('#').map_or(url, |(url, _)| url);
+    let uri: Uri = url.parse().ok()?;
+    uri.host().map(str::to_ascii_lowercase)
+}
+
+// whether `host` is one of `domains` or a subdomain of one
+fn on_domains(domains: &HashSet<String>, host: &str) -> bool {
+    // check the host and every parent domain: a.b.evil.com, b.evil.com, evil.com, com
+    let mut domain = host;
+    loop {
+        if domains.contains(domain) {
+            return true;
+        }
+        match domain.split_once('.') {
+            Some((_, parent)) => domain = parent,
+            None => return false,
+        }
+    }
+}
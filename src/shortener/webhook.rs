@@ -0,0 +1,646 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use http::{header::CONTENT_TYPE, StatusCode, Uri};
+use moka::sync::Cache;
+use nanoid::nanoid;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use super::{
+    Admin, AppError, AppJson, AppState, Click, Owner, ProblemDetails, StoreError, UrlStore,
+};
+
+const SECRET_PREFIX: &str = "whsec_";
+const EVENT_QUEUE_SIZE: usize = 1024;
+const MAX_CONCURRENT_DELIVERIES: usize = 16;
+// deliveries being sent or waiting for a retry, more are dead lettered right away
+const MAX_PENDING_DELIVERIES: usize = 1024;
+// links whose webhooks are remembered, and for how long: webhooks changed
+// through another instance are picked up after that
+const WEBHOOK_CACHE_CAPACITY: u64 = 10_000;
+const WEBHOOK_CACHE_TTL: Duration = Duration::from_secs(60);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+// newest first, older ones are only in the database
+const DEAD_LETTER_LIMIT: u32 = 100;
+
+/// Header carrying the event type, e.g. `click`.
+pub const WEBHOOK_EVENT_HEADER: &str = "x-shortener-event";
+/// Header carrying `t=<unix timestamp>,v1=<signature>`, see [`sign_delivery`].
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "x-shortener-signature";
+
+/// An endpoint notified of the clicks on one link, or on every link of its
+/// owner when `link_id` is `None`.
+#[derive(Debug, Clone, FromRow)]
+pub struct Webhook {
+    pub id: String,
+    pub owner: String,
+    pub link_id: Option<String>,
+    pub url: String,
+    /// signs the deliveries, see [`sign_delivery`]
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateWebhookReq {
+    pub url: String,
+    /// the link to notify about, every link of the api key when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateWebhookRes {
+    pub id: String,
+    /// shown only once, it can't be recovered later
+    pub secret: String,
+}
+
+/// A registered webhook as listed to its owner, without the secret.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WebhookRes {
+    pub id: String,
+    pub link_id: Option<String>,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The json body of a click delivery.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClickEvent {
+    /// the same on every attempt, receivers can use it to drop duplicates
+    pub id: String,
+    /// always `click`
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub link_id: String,
+    pub clicked_at: DateTime<Utc>,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    /// the rule or split variant the click was sent to
+    pub variant: Option<String>,
+}
+
+/// A delivery that failed every attempt.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct DeadLetter {
+    pub webhook_id: String,
+    pub url: String,
+    /// the event as it was sent
+    pub payload: String,
+    pub attempts: i32,
+    /// why the last attempt failed
+    pub error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// How often and how patiently deliveries are retried: the n-th retry waits
+/// `base_delay * 2^(n-1)`, at most `max_delay`.
+#[derive(Debug, Clone, Copy)]
+pub struct WebhookRetry {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+/// How the deliveries of a [`WebhookQueue`] are made.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DeliveryPolicy {
+    pub(crate) retry: WebhookRetry,
+    /// deliver to loopback, link-local and private addresses too
+    pub(crate) private_targets: bool,
+    /// once cancelled, deliveries waiting for a retry are dead lettered instead
+    pub(crate) shutdown: CancellationToken,
+}
+
+/// Queues clicks for webhook delivery without waiting on it. A background
+/// task looks up the webhooks of each click and delivers to them.
+#[derive(Debug, Clone)]
+pub(crate) struct WebhookQueue {
+    events: mpsc::Sender<Click>,
+    // the webhooks of recently clicked links, empty for most, so clicks don't
+    // cost a store lookup each
+    webhooks: Cache<String, Arc<[Webhook]>>,
+}
+
+// resolves hosts for deliveries, refusing those with an internal address so
+// a host can't be pointed at one after its webhook was registered
+#[derive(Debug)]
+struct PublicResolver;
+
+impl Default for WebhookRetry {
+    fn default() -> Self {
+        // about 10 minutes from the first attempt to the dead letter
+        Self {
+            max_attempts: 8,
+            base_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl WebhookRetry {
+    fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+impl Webhook {
+    /// A new webhook with a fresh secret.
+    pub fn generate(owner: impl Into<String>, link_id: Option<String>, url: String) -> Self {
+        let key: [u8; 32] = rand::random();
+        Self {
+            id: nanoid!(8),
+            owner: owner.into(),
+            link_id,
+            url,
+            secret: format!("{SECRET_PREFIX}{}", blake3::Hash::from(key).to_hex()),
+            created_at: Utc::now(),
+        }
+    }
+}
+
+impl From<Webhook> for WebhookRes {
+    fn from(webhook: Webhook) -> Self {
+        Self {
+            id: webhook.id,
+            link_id: webhook.link_id,
+            url: webhook.url,
+            created_at: webhook.created_at,
+        }
+    }
+}
+
+impl From<&Click> for ClickEvent {
+    fn from(click: &Click) -> Self {
+        Self {
+            id: nanoid!(),
+            kind: "click".to_string(),
+            link_id: click.url_id.clone(),
+            clicked_at: click.clicked_at,
+            referrer: click.referrer.clone(),
+            user_agent: click.user_agent.clone(),
+            variant: click.variant.clone(),
+        }
+    }
+}
+
+/// Signs a delivery: the hex blake3 keyed hash of `{timestamp}.{body}`, keyed
+/// with the 32 bytes the secret encodes. `None` if the secret is malformed.
+pub fn sign_delivery(secret: &str, timestamp: i64, body: &[u8]) -> Option<String> {
+    let key = blake3::Hash::from_hex(secret.strip_prefix(SECRET_PREFIX)?).ok()?;
+    let mut hasher = blake3::Hasher::new_keyed(key.as_bytes());
+    hasher.update(timestamp.to_string().as_bytes());
+    hasher.update(b".");
+    hasher.update(body);
+    Some(hasher.finalize().to_hex().to_string())
+}
+
+/// Checks the signature header of a delivery, returning when it was signed.
+/// Receivers should also reject old timestamps, so deliveries can't be replayed.
+pub fn verify_delivery(secret: &str, signature_header: &str, body: &[u8]) -> Option<DateTime<Utc>> {
+    let mut timestamp = None;
+    let mut signature = None;
+    for part in signature_header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", t)) => timestamp = t.parse::<i64>().ok(),
+            Some(("v1", v1)) => signature = blake3::Hash::from_hex(v1).ok(),
+            _ => {}
+        }
+    }
+    let (timestamp, signature) = (timestamp?, signature?);
+    let expected = blake3::Hash::from_hex(sign_delivery(secret, timestamp, body)?).ok()?;
+    // blake3::Hash compares in constant time
+    (signature == expected)
+        .then(|| DateTime::from_timestamp(timestamp, 0))
+        .flatten()
+}
+
+impl WebhookQueue {
+    /// Must be called within a tokio runtime, it spawns the delivery task
+    /// (and the deliveries) on `tasks`.
+    pub(crate) fn new(
+        store: Arc<dyn UrlStore>,
+        policy: DeliveryPolicy,
+        tasks: TaskTracker,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(EVENT_QUEUE_SIZE);
+        let webhooks = Cache::builder()
+            .max_capacity(WEBHOOK_CACHE_CAPACITY)
+            .time_to_live(WEBHOOK_CACHE_TTL)
+            .build();
+        // the task only gets the cache: holding the sender would keep it running
+        let deliver = deliver_events(store, rx, webhooks.clone(), policy, tasks.clone());
+        tasks.spawn(deliver);
+        Self {
+            events: tx,
+            webhooks,
+        }
+    }
+
+    pub(crate) fn enqueue(&self, click: &Click) {
+        if let Err(e) = self.events.try_send(click.clone()) {
+            warn!("Dropping webhook event: {}", e);
+        }
+    }
+
+    /// Forgets the webhooks of link `id`, e.g. once it is deleted.
+    pub(crate) fn invalidate(&self, id: &str) {
+        self.webhooks.invalidate(id);
+    }
+
+    /// Forgets the webhooks of every link, a webhook of an owner notifies of
+    /// all its links.
+    pub(crate) fn invalidate_all(&self) {
+        self.webhooks.invalidate_all();
+    }
+}
+
+/// Registers a webhook for the clicks on a link, or on every link of the api key
+#[utoipa::path(
+    post,
+    path = "/webhooks",
+    tag = "webhooks",
+    request_body(content = CreateWebhookReq, example = json!({"url": "https://hooks.example.com/clicks"})),
+    responses(
+        (status = 201, description = "The webhook, its secret shown only this once", body = CreateWebhookRes),
+        (status = 400, description = "Malformed json", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or unknown api key", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "The link belongs to another api key", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such link", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid url, or one pointing at an internal address", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn create_webhook(
+    State(state): State<AppState>,
+    Owner(owner): Owner,
+    AppJson(data): AppJson<CreateWebhookReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let url = state.urls.normalize(&data.url)?;
+    if !state.delivery.private_targets {
+        check_target(&url)
+            .await
+            .map_err(|e| AppError::Validation(format!("webhook url {e}")))?;
+    }
+    if let Some(link_id) = &data.link_id {
+        let link = state
+            .store
+            .find_link(link_id)
+            .await
+            .map_err(AppError::store(format!("failed to find {link_id}")))?;
+        if link.owner.as_deref() != Some(owner.as_str()) {
+            let e = StoreError::NotOwner(link_id.clone());
+            return Err(AppError::Forbidden(e.to_string()));
+        }
+    }
+    let webhook = Webhook::generate(owner, data.link_id, url);
+    state
+        .store
+        .create_webhook(&webhook)
+        .await
+        .map_err(AppError::store("failed to create webhook"))?;
+    state.webhooks.invalidate_all();
+    let body = Json(CreateWebhookRes {
+        id: webhook.id,
+        secret: webhook.secret,
+    });
+    Ok((StatusCode::CREATED, body))
+}
+
+/// Webhooks of the api key
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "The webhooks, without their secrets", body = Vec<WebhookRes>),
+        (status = 401, description = "Missing or unknown api key", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn list_webhooks(
+    State(state): State<AppState>,
+    Owner(owner): Owner,
+) -> Result<Json<Vec<WebhookRes>>, AppError> {
+    let webhooks = state
+        .store
+        .list_webhooks(&owner)
+        .await
+        .map_err(AppError::store("failed to list webhooks"))?;
+    Ok(Json(webhooks.into_iter().map(WebhookRes::from).collect()))
+}
+
+/// Deletes a webhook
+#[utoipa::path(
+    delete,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "id of the webhook")),
+    responses(
+        (status = 204, description = "The webhook was deleted"),
+        (status = 401, description = "Missing or unknown api key", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such webhook of the api key", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn delete_webhook(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Owner(owner): Owner,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .store
+        .delete_webhook(&id, &owner)
+        .await
+        .map_err(AppError::store(format!("failed to delete webhook {id}")))?;
+    state.webhooks.invalidate_all();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Deliveries that failed every attempt, newest first
+#[utoipa::path(
+    get,
+    path = "/admin/dead-letters",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The newest dead letters", body = Vec<DeadLetter>),
+        (status = 401, description = "Missing or wrong admin token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "The admin api is disabled", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("admin_token" = []))
+)]
+pub(super) async fn dead_letters(
+    State(state): State<AppState>,
+    _: Admin,
+) -> Result<Json<Vec<DeadLetter>>, AppError> {
+    let dead_letters = state
+        .store
+        .dead_letters(DEAD_LETTER_LIMIT)
+        .await
+        .map_err(AppError::store("failed to list dead letters"))?;
+    Ok(Json(dead_letters))
+}
+
+/// Looks up the webhooks of each queued click and delivers to them, every
+/// delivery retrying on its own so a slow endpoint holds up no other.
+async fn deliver_events(
+    store: Arc<dyn UrlStore>,
+    mut rx: mpsc::Receiver<Click>,
+    cache: Cache<String, Arc<[Webhook]>>,
+    policy: DeliveryPolicy,
+    tasks: TaskTracker,
+) {
+    // redirects are not followed, they could lead anywhere
+    let mut client = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+    if !policy.private_targets {
+        client = client.dns_resolver(Arc::new(PublicResolver));
+    }
+    let client = match client.build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!(
+                "Failed to build the webhook client, no webhook will be delivered: {:?}",
+                e
+            );
+            return;
+        }
+    };
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_DELIVERIES));
+    let pending = Arc::new(Semaphore::new(MAX_PENDING_DELIVERIES));
+    while let Some(click) = rx.recv().await {
+        let webhooks = match find_webhooks(&cache, store.as_ref(), &click.url_id).await {
+            Ok(webhooks) => webhooks,
+            Err(e) => {
+                warn!("Failed to look up webhooks of {}: {:?}", click.url_id, e);
+                continue;
+            }
+        };
+        if webhooks.is_empty() {
+            continue;
+        }
+        let payload = match serde_json::to_string(&ClickEvent::from(&click)) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("Failed to encode click event: {:?}", e);
+                continue;
+            }
+        };
+        for webhook in webhooks.iter().cloned() {
+            let delivery = Delivery {
+                store: store.clone(),
+                client: client.clone(),
+                permits: permits.clone(),
+                policy: policy.clone(),
+            };
+            match pending.clone().try_acquire_owned() {
+                Ok(pending) => tasks.spawn(delivery.run(webhook, payload.clone(), pending)),
+                // endpoints failing for long pile up retries, keep the rest for later
+                Err(_) => tasks.spawn(delivery.dead_letter(
+                    webhook,
+                    payload.clone(),
+                    0,
+                    "too many deliveries pending".to_string(),
+                )),
+            };
+        }
+    }
+}
+
+// the webhooks of link `id`, from the cache or the store
+async fn find_webhooks(
+    cache: &Cache<String, Arc<[Webhook]>>,
+    store: &dyn UrlStore,
+    id: &str,
+) -> Result<Arc<[Webhook]>, StoreError> {
+    if let Some(webhooks) = cache.get(id) {
+        return Ok(webhooks);
+    }
+    let webhooks: Arc<[Webhook]> = store.find_webhooks(id).await?.into();
+    cache.insert(id.to_string(), webhooks.clone());
+    Ok(webhooks)
+}
+
+// everything one delivery needs, shared with the others
+struct Delivery {
+    store: Arc<dyn UrlStore>,
+    client: reqwest::Client,
+    permits: Arc<Semaphore>,
+    policy: DeliveryPolicy,
+}
+
+impl Delivery {
+    // `_pending` is held until the delivery is done, one way or the other
+    async fn run(self, webhook: Webhook, payload: String, _pending: OwnedSemaphorePermit) {
+        let mut attempts = 0;
+        let error = loop {
+            attempts += 1;
+            let ret = match self.permits.acquire().await {
+                Ok(_permit) => self.send(&webhook, &payload).await,
+                // the semaphore is never closed
+                Err(e) => Err(e.to_string()),
+            };
+            let error = match ret {
+                Ok(()) => return,
+                Err(error) => error,
+            };
+            if attempts >= self.policy.retry.max_attempts {
+                break error;
+            }
+            // the retry may never come, keep the delivery as a dead letter
+            tokio::select! {
+                _ = tokio::time::sleep(self.policy.retry.delay(attempts)) => {}
+                _ = self.policy.shutdown.cancelled() => {
+                    break format!("{error}, shut down before the next attempt");
+                }
+            }
+        };
+        self.dead_letter(webhook, payload, attempts, error).await;
+    }
+
+    async fn dead_letter(self, webhook: Webhook, payload: String, attempts: u32, error: String) {
+        warn!(
+            "Giving up on webhook {} after {} attempts: {}",
+            webhook.id, attempts, error
+        );
+        let dead_letter = DeadLetter {
+            webhook_id: webhook.id,
+            url: webhook.url,
+            payload,
+            attempts: attempts as i32,
+            error,
+            failed_at: Utc::now(),
+        };
+        if let Err(e) = self.store.insert_dead_letter(&dead_letter).await {
+            warn!("Failed to store dead letter: {:?}", e);
+        }
+    }
+
+    async fn send(&self, webhook: &Webhook, payload: &str) -> Result<(), String> {
+        let timestamp = Utc::now().timestamp();
+        let signature = sign_delivery(&webhook.secret, timestamp, payload.as_bytes())
+            .ok_or_else(|| "malformed secret".to_string())?;
+        // the resolver only sees hosts, not ip addresses
+        if !self.policy.private_targets {
+            check_target(&webhook.url).await?;
+        }
+        let res = self
+            .client
+            .post(&webhook.url)
+            .timeout(DELIVERY_TIMEOUT)
+            .header(CONTENT_TYPE, "application/json")
+            .header(WEBHOOK_EVENT_HEADER, "click")
+            .header(
+                WEBHOOK_SIGNATURE_HEADER,
+                format!("t={timestamp},v1={signature}"),
+            )
+            .body(payload.to_string())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("endpoint responded with {}", res.status()))
+        }
+    }
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = resolve_public(name.as_str(), 0).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Fails if the host of `url` is, or resolves to, an internal address.
+async fn check_target(url: &str) -> Result<(), String> {
+    let uri: Uri = url.parse().map_err(|e| format!("is invalid: {e}"))?;
+    let host = uri.host().ok_or_else(|| "has no host".to_string())?;
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) if is_internal(ip) => Err(format!("points at internal address {ip}")),
+        Ok(_) => Ok(()),
+        Err(_) => resolve_public(host, uri.port_u16().unwrap_or(0))
+            .await
+            .map(drop),
+    }
+}
+
+// every address of `host`, unless one of them is internal
+async fn resolve_public(host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let addrs: Vec<_> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("host {host} doesn't resolve: {e}"))?
+        .collect();
+    if let Some(addr) = addrs.iter().find(|addr| is_internal(addr.ip())) {
+        return Err(format!(
+            "host {host} resolves to internal address {}",
+            addr.ip()
+        ));
+    }
+    if addrs.is_empty() {
+        return Err(format!("host {host} doesn't resolve"));
+    }
+    Ok(addrs)
+}
+
+// loopback, private, link-local (cloud metadata lives there) and other
+// addresses that aren't on the public internet
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // "this network", carrier-grade nat, benchmarking and reserved
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 198 && (18..20).contains(&b))
+                || a >= 240
+        }
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(ip) => is_internal(IpAddr::V4(ip)),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+            }
+        },
+    }
+}
+
+// the ipv4 address an ipv6 one reaches: v4-mapped `::ffff:a.b.c.d`,
+// v4-compatible `::a.b.c.d`, nat64 `64:ff9b::a.b.c.d` and 6to4 `2002:aabb:ccdd::`
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let [.., a, b, c, d] = ip.octets();
+    match ip.segments() {
+        [0x2002, high, low, ..] => Some(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low))),
+        [0x64, 0xff9b, 0, 0, 0, 0, ..] => Some(Ipv4Addr::new(a, b, c, d)),
+        _ => ip.to_ipv4(),
+    }
+}
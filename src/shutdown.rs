@@ -0,0 +1,163 @@
+use std::{
+    future::IntoFuture,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use tokio::{signal, task::JoinHandle, time::Instant};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{info, warn};
+
+/// How long servers get to finish what they are doing once asked to stop.
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(30);
+
+/// Tells a server's tasks to stop, and waits for them within a grace period.
+/// Cloning shares the token, the tasks and the grace period: draining and
+/// waiting together take no longer than it.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    token: CancellationToken,
+    tasks: TaskTracker,
+    grace: Duration,
+    // when the grace period started counting down
+    started_at: Arc<OnceLock<Instant>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new(DEFAULT_GRACE)
+    }
+}
+
+impl Shutdown {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            token: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+            grace,
+            started_at: Arc::default(),
+        }
+    }
+
+    /// Starts the shutdown on the first SIGINT (ctrl-c) or SIGTERM. Must be
+    /// called within a tokio runtime, it spawns the task waiting for them.
+    pub fn on_signal(self) -> Self {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = signal() => {
+                    info!("Shutting down, waiting for open work to finish");
+                    shutdown.trigger();
+                }
+                _ = shutdown.token.cancelled() => {}
+            }
+        });
+        self
+    }
+
+    pub fn grace(&self) -> Duration {
+        self.grace
+    }
+
+    /// Cancelled once the shutdown started, child tokens of it too.
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Starts the shutdown, as a signal would.
+    pub fn trigger(&self) {
+        self.deadline();
+        self.token.cancel();
+    }
+
+    /// Resolves once the shutdown started, e.g. for `with_graceful_shutdown`.
+    pub async fn started(self) {
+        self.token.cancelled().await
+    }
+
+    /// Spawns a task [`Shutdown::wait`] waits for.
+    pub fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
+    where
+        F: std::future::Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.tasks.spawn(task)
+    }
+
+    /// Runs `work` to the end, unless it's still running the grace period
+    /// after the shutdown started. `None` if it was cut short.
+    pub async fn drain<F: IntoFuture>(&self, work: F) -> Option<F::Output> {
+        let work = work.into_future();
+        tokio::pin!(work);
+        tokio::select! {
+            ret = &mut work => return Some(ret),
+            _ = self.token.cancelled() => {}
+        }
+        match tokio::time::timeout_at(self.deadline(), work).await {
+            Ok(ret) => Some(ret),
+            Err(_) => {
+                warn!("Gave up on open work after {:?}", self.grace);
+                None
+            }
+        }
+    }
+
+    /// Waits for the spawned tasks, and those of `others`, until the grace
+    /// period is over, counting from the start of the shutdown (or from now if
+    /// it hasn't started). No task can be spawned on them afterwards. Whether
+    /// all of them finished in time.
+    pub async fn wait<'a>(&self, others: impl IntoIterator<Item = &'a TaskTracker>) -> bool {
+        let mut trackers: Vec<_> = others.into_iter().cloned().collect();
+        trackers.push(self.tasks.clone());
+        for tasks in &trackers {
+            tasks.close();
+        }
+        let all = futures::future::join_all(trackers.iter().map(|tasks| tasks.wait()));
+        match tokio::time::timeout_at(self.deadline(), all).await {
+            Ok(_) => true,
+            Err(_) => {
+                let left: usize = trackers.iter().map(|tasks| tasks.len()).sum();
+                warn!("Gave up on {} tasks after {:?}", left, self.grace);
+                false
+            }
+        }
+    }
+
+    // the end of the grace period, which starts counting down the first time
+    // it is asked for: on trigger, or once a drain sees the token cancelled
+    fn deadline(&self) -> Instant {
+        *self.started_at.get_or_init(Instant::now) + self.grace
+    }
+}
+
+/// Resolves on the first SIGINT (ctrl-c) or, on unix, SIGTERM.
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = signal::ctrl_c().await {
+            warn!("Failed to listen for ctrl-c: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}